@@ -0,0 +1,56 @@
+//! Embeds the directory given by `SIMPLE_HTTP_SERVER_EMBED_DIR` (if set) into the binary.
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EMBED_DIR_VAR: &str = "SIMPLE_HTTP_SERVER_EMBED_DIR";
+
+fn main() {
+	println!("cargo:rerun-if-env-changed={EMBED_DIR_VAR}");
+
+	let mut files = vec![];
+	if let Some(dir) = env::var_os(EMBED_DIR_VAR).filter(|d| !d.is_empty()) {
+		let dir = fs::canonicalize(&dir).unwrap_or_else(|e| {
+			panic!("failed to access the embed directory {dir:?}: {e}");
+		});
+		println!("cargo:rerun-if-changed={}", dir.display());
+		collect_files(&dir, &dir, &mut files);
+	}
+	files.sort();
+
+	let mut out = String::from("static FILES: &[(&str, &[u8])] = &[\n");
+	for (name, path) in files {
+		writeln!(out, "\t({name:?}, include_bytes!({path:?})),").unwrap();
+	}
+	out.push_str("];\n");
+
+	let out_path = Path::new(&env::var_os("OUT_DIR").unwrap()).join("embedded.rs");
+	fs::write(out_path, out).unwrap();
+}
+
+/// Add all files in `dir` (recursively) as `(name, path)`,
+/// where `name` is the path relative to `root` with `/` as the separator.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) {
+	let entries = fs::read_dir(dir).unwrap_or_else(|e| panic!("failed to read {dir:?}: {e}"));
+	for entry in entries {
+		let path = entry.unwrap().path();
+		if path.is_dir() {
+			collect_files(root, &path, files);
+			continue;
+		}
+		let name = path
+			.strip_prefix(root)
+			.unwrap()
+			.components()
+			.map(|c| {
+				c.as_os_str()
+					.to_str()
+					.expect("embedded paths must be UTF-8")
+			})
+			.collect::<Vec<_>>()
+			.join("/");
+		files.push((name, path));
+	}
+}
@@ -0,0 +1,8051 @@
+//! The server behind the `simple-http-server` binary, for embedding it into other programs.
+//!
+//! A [`Config`] is loaded from a config file (see the README for its format) and then served with [`serve`],
+//! which runs until the server is shut down (e.g. by SIGINT or SIGTERM).
+
+pub use config::{Config, ConfigBuilder, ConfigContent, ConfigError};
+pub use http::{serve, serve_with_fallback, FallbackHandler};
+
+// note the intentional distinction between stdout and stderr:
+// stdout is only for things that should be considered *output* of the program,
+// so all info, warning and error messages go to stderr.
+//
+// also note that there is no context (like `tracing` or `async-log`) for the logs,
+// but that's fine since all log messages are atomic.
+pub mod log {
+	use std::fmt::Display;
+	use std::fs::{File, OpenOptions};
+	use std::io::Write;
+	use std::net::IpAddr;
+	use std::path::Path;
+	use std::process::exit;
+	use std::str::FromStr;
+	use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+	use std::sync::{Arc, Mutex, RwLock};
+	use std::time::{Duration, Instant, SystemTime};
+
+	use axum::http::header::{REFERER, USER_AGENT};
+	use axum::http::{HeaderValue, Method, Request, Uri, Version};
+
+	use serde::Deserialize;
+	use serde_json::json;
+
+	use super::cli::{
+		CHECK_FLAG, DEV_FLAG, DUMP_CONFIG_FLAG, LIST_ROUTES_FLAG, PORT_FLAG, PRINT_README_FLAG,
+		QUIET_FLAG, VERBOSE_FLAG, WATCH_FLAG,
+	};
+
+	pub fn print_readme() -> ! {
+		println!("{}", include_str!("../README.md"));
+		exit(0)
+	}
+
+	pub struct CliMessages(pub Option<String>);
+
+	impl CliMessages {
+		pub fn print_usage(&self, success: bool) -> ! {
+			let this = self.0.as_deref().unwrap_or("<this>");
+
+			let output = format!(
+				"USAGE:
+{this} [<options>] [--] <path to config file>
+	Run the server normally
+{this} --{CHECK_FLAG} [<options>] [--] <path to config file>
+	Only check the config file and exit
+	(with a nonzero exit code if it is invalid)
+{this} --{DUMP_CONFIG_FLAG} [<options>] [--] <path to config file>
+	Print the processed config (addresses, routes, error pages) and exit
+{this} -h|--help
+	Show this message and exit
+{this} --{PRINT_README_FLAG}
+	Write out this software's documentation
+	in the form of a README.md file (to stdout)
+
+OPTIONS:
+-p|--{PORT_FLAG} <port>
+	Override the port of `addr` (and ignore `failsafe_addrs`)
+--{LIST_ROUTES_FLAG}
+	Log all routes (with their files and MIME types) at startup
+-q|--{QUIET_FLAG}
+	Only log errors
+-v|--{VERBOSE_FLAG}
+	Log everything (info messages and requests included)
+--{WATCH_FLAG}
+	Log changes to the served files and stop caching changed files (for development)
+--{DEV_FLAG}
+	Like --{WATCH_FLAG}, but also reload HTML pages in the browser when files change"
+			);
+			if success {
+				println!("{output}");
+			} else {
+				eprintln!("{output}");
+			}
+			std::process::exit(!success as i32)
+		}
+
+		#[inline]
+		pub fn print_help(&self) -> ! {
+			println!(concat!("simple-http-server v", env!("CARGO_PKG_VERSION")));
+			self.print_usage(true)
+		}
+
+		#[inline(always)]
+		pub fn err(&self, msg: impl std::fmt::Display) -> ! {
+			error(msg);
+			eprintln!(/* blank line for spacing */);
+			self.print_usage(false)
+		}
+
+		#[inline]
+		pub fn err_missing_config(&self) -> ! {
+			self.err("missing config argument")
+		}
+
+		#[inline]
+		pub fn err_invalid(&self, s: &str, double: bool) -> ! {
+			self.err(format_args!(
+				"`-{}{s}` is invalid",
+				if double { "-" } else { "" }
+			))
+		}
+	}
+
+	#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+	#[serde(rename_all = "lowercase")]
+	pub enum LogFormat {
+		/// Human-readable lines
+		#[default]
+		Text,
+		/// One JSON object per line
+		Json,
+	}
+
+	/// Which messages are logged (each level includes the ones before it)
+	#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Deserialize)]
+	#[serde(rename_all = "lowercase")]
+	pub enum LogLevel {
+		Error,
+		#[default]
+		Warn,
+		/// Info messages and requests
+		Info,
+		/// Also requests that are usually just noise (e.g. for a missing `/favicon.ico`)
+		Debug,
+	}
+
+	static JSON: AtomicBool = AtomicBool::new(false);
+	static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Warn as u8);
+
+	pub fn set_format(format: LogFormat) {
+		JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+	}
+
+	pub fn set_level(level: LogLevel) {
+		LEVEL.store(level as u8, Ordering::Relaxed);
+	}
+
+	fn enabled(level: LogLevel) -> bool {
+		level as u8 <= LEVEL.load(Ordering::Relaxed)
+	}
+
+	fn timestamp() -> String {
+		humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+	}
+
+	/// The file that diagnostics (info, warnings and errors) go to instead of stderr
+	static DIAGNOSTICS_FILE: RwLock<Option<Arc<File>>> = RwLock::new(None);
+	/// The file that requests go to instead of stdout
+	static REQUESTS_FILE: RwLock<Option<Arc<File>>> = RwLock::new(None);
+
+	/// Set the files the logs are written to.
+	///
+	/// Without an `access_log_file`, requests are logged to the `log_file` as well.
+	/// If a file can't be opened, the corresponding standard stream is used instead.
+	pub fn set_files(log_file: Option<&Path>, access_log_file: Option<&Path>) {
+		let open = |path: &Path| {
+			OpenOptions::new()
+				.create(true)
+				.append(true)
+				.open(path)
+				.map(Arc::new)
+				.map_err(|e| warn(format_args!("failed to open log file {path:?} ({e})")))
+				.ok()
+		};
+		let diagnostics = log_file.and_then(open);
+		let requests = match access_log_file {
+			Some(path) => open(path),
+			None => diagnostics.clone(),
+		};
+		*DIAGNOSTICS_FILE.write().unwrap_or_else(|e| e.into_inner()) = diagnostics;
+		*REQUESTS_FILE.write().unwrap_or_else(|e| e.into_inner()) = requests;
+	}
+
+	/// Write `line` to `file` if it is set, otherwise (or on failure) to the standard stream `fallback`.
+	///
+	/// If `timestamped` is set, lines in the text format get a timestamp in files.
+	fn emit(
+		file: &RwLock<Option<Arc<File>>>,
+		mut fallback: impl Write,
+		line: impl Display,
+		timestamped: bool,
+	) {
+		let file = file.read().unwrap_or_else(|e| e.into_inner()).clone();
+		if let Some(file) = file {
+			// lines in files get a timestamp (which JSON lines already have)
+			let line = if JSON.load(Ordering::Relaxed) || !timestamped {
+				format!("{line}\n")
+			} else {
+				format!("{} {line}\n", timestamp())
+			};
+			// a single write, so that concurrent lines don't get mixed up
+			if (&*file).write_all(line.as_bytes()).is_ok() {
+				return;
+			}
+		}
+		let _ = writeln!(fallback, "{line}");
+	}
+
+	fn diagnostic(level: &str, m: impl Display) {
+		if JSON.load(Ordering::Relaxed) {
+			let line = json!({
+				"timestamp": timestamp(),
+				"level": level,
+				"message": m.to_string(),
+			});
+			emit(&DIAGNOSTICS_FILE, std::io::stderr(), line, true);
+		} else {
+			emit(
+				&DIAGNOSTICS_FILE,
+				std::io::stderr(),
+				format_args!("[{level}] {m}"),
+				true,
+			);
+		}
+	}
+
+	pub fn error(e: impl Display) {
+		diagnostic("error", e);
+	}
+
+	pub fn warn(w: impl Display) {
+		if enabled(LogLevel::Warn) {
+			diagnostic("warn", w);
+		}
+	}
+
+	pub fn info(i: impl Display) {
+		if enabled(LogLevel::Info) {
+			diagnostic("info", i);
+		}
+	}
+
+	/// An info message that was explicitly asked for (e.g. with `--list-routes`), so it is logged at every level
+	pub fn requested(i: impl Display) {
+		diagnostic("info", i);
+	}
+
+	/// The start of the current window and the number of 404 responses in it (for `log_404_summary_secs`)
+	static NOT_FOUND: Mutex<Option<(Instant, u64)>> = Mutex::new(None);
+
+	/// Count a request with a 404 response instead of logging it,
+	/// and log the count once `window` has passed since the first one that was counted.
+	pub fn count_not_found(window: Duration) {
+		if !enabled(LogLevel::Info) {
+			return;
+		}
+		let mut not_found = NOT_FOUND.lock().unwrap_or_else(|e| e.into_inner());
+		let now = Instant::now();
+		let (start, count) = not_found.get_or_insert((now, 0));
+		*count += 1;
+		let elapsed = now.duration_since(*start);
+		if elapsed >= window {
+			let secs = elapsed.as_secs();
+			info(format_args!(
+				"{count} request(s) got a 404 response in the last {secs}s"
+			));
+			*not_found = None;
+		}
+	}
+
+	/// A handled request, for the request log
+	pub struct RequestLog {
+		/// The address of the client (if known)
+		pub client: Option<IpAddr>,
+		pub method: Method,
+		pub uri: Uri,
+		pub version: Version,
+		/// The scheme that the client used (`http` or `https`)
+		pub scheme: &'static str,
+		pub referer: Option<HeaderValue>,
+		pub user_agent: Option<HeaderValue>,
+		pub status: u16,
+		/// The length of the response body
+		pub bytes: u64,
+		/// The time it took to handle the request (without sending the body, which may be streamed)
+		pub duration: Duration,
+		/// What was done with the request
+		pub note: String,
+	}
+
+	impl RequestLog {
+		/// The log of `request` (from `client` via `scheme`), with the response fields still unset
+		pub fn new<B>(request: &Request<B>, client: Option<IpAddr>, scheme: &'static str) -> Self {
+			Self {
+				client,
+				method: request.method().clone(),
+				uri: request.uri().clone(),
+				version: request.version(),
+				scheme,
+				referer: request.headers().get(REFERER).cloned(),
+				user_agent: request.headers().get(USER_AGENT).cloned(),
+				status: 0,
+				bytes: 0,
+				duration: Duration::ZERO,
+				note: String::new(),
+			}
+		}
+	}
+
+	/// The `log_format_template` that is used for request logs in the text format
+	static TEMPLATE: RwLock<Option<Template>> = RwLock::new(None);
+
+	pub fn set_template(template: Option<Template>) {
+		*TEMPLATE.write().unwrap_or_else(|e| e.into_inner()) = template;
+	}
+
+	/// The Common Log Format (as used by Apache and nginx)
+	const COMMON_TEMPLATE: &str =
+		r#"{ip} - - [{time}] "{method} {uri} {version}" {status} {bytes}"#;
+	/// The Combined Log Format, which adds the referer and user agent to the Common Log Format
+	const COMBINED_TEMPLATE: &str = r#"{ip} - - [{time}] "{method} {uri} {version}" {status} {bytes} "{referer}" "{user_agent}""#;
+
+	/// A parsed `log_format_template`
+	#[derive(Debug, Clone, Eq, PartialEq)]
+	pub struct Template(Vec<Segment>);
+
+	#[derive(Debug, Clone, Eq, PartialEq)]
+	enum Segment {
+		Literal(String),
+		Field(Field),
+	}
+
+	#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+	enum Field {
+		Ip,
+		Method,
+		Uri,
+		Version,
+		Scheme,
+		Status,
+		Bytes,
+		Ms,
+		Time,
+		Referer,
+		UserAgent,
+		Note,
+	}
+
+	impl FromStr for Template {
+		type Err = String;
+
+		/// Parse a template with `{field}` placeholders (and `{{` and `}}` for literal braces),
+		/// or one of the presets `common` and `combined`
+		fn from_str(s: &str) -> Result<Self, String> {
+			let s = match s {
+				"common" => COMMON_TEMPLATE,
+				"combined" => COMBINED_TEMPLATE,
+				s => s,
+			};
+			let mut segments = vec![];
+			let mut literal = String::new();
+			let mut chars = s.chars();
+			while let Some(c) = chars.next() {
+				match c {
+					'{' if chars.as_str().starts_with('{') => {
+						chars.next();
+						literal.push('{');
+					}
+					'}' if chars.as_str().starts_with('}') => {
+						chars.next();
+						literal.push('}');
+					}
+					'{' => {
+						let rest = chars.as_str();
+						let Some(end) = rest.find('}') else {
+							return Err("unclosed `{` in `log_format_template`".to_string());
+						};
+						let field = match &rest[..end] {
+							"ip" => Field::Ip,
+							"method" => Field::Method,
+							"uri" => Field::Uri,
+							"version" => Field::Version,
+							"scheme" => Field::Scheme,
+							"status" => Field::Status,
+							"bytes" => Field::Bytes,
+							"ms" => Field::Ms,
+							"time" => Field::Time,
+							"referer" => Field::Referer,
+							"user_agent" => Field::UserAgent,
+							"note" => Field::Note,
+							name => {
+								return Err(format!(
+									"unknown placeholder {{{name}}} in `log_format_template`"
+								))
+							}
+						};
+						chars = rest[end + 1..].chars();
+						if !literal.is_empty() {
+							segments.push(Segment::Literal(std::mem::take(&mut literal)));
+						}
+						segments.push(Segment::Field(field));
+					}
+					'}' => return Err("unmatched `}` in `log_format_template`".to_string()),
+					c => literal.push(c),
+				}
+			}
+			if !literal.is_empty() {
+				segments.push(Segment::Literal(literal));
+			}
+			Ok(Self(segments))
+		}
+	}
+
+	impl Template {
+		/// The log line for `log`, with `-` for missing values
+		fn format(&self, log: &RequestLog) -> String {
+			let header = |v: &Option<HeaderValue>| {
+				let v = v.as_ref().and_then(|v| v.to_str().ok());
+				v.unwrap_or("-").to_string()
+			};
+			let mut line = String::new();
+			for segment in &self.0 {
+				let field = match segment {
+					Segment::Literal(s) => {
+						line += s;
+						continue;
+					}
+					Segment::Field(field) => field,
+				};
+				line += &match field {
+					Field::Ip => log.client.map_or("-".to_string(), |c| c.to_string()),
+					Field::Method => log.method.to_string(),
+					Field::Uri => log.uri.to_string(),
+					Field::Version => format!("{:?}", log.version),
+					Field::Scheme => log.scheme.to_string(),
+					Field::Status => log.status.to_string(),
+					Field::Bytes => log.bytes.to_string(),
+					Field::Ms => format!("{:.3}", log.duration.as_secs_f64() * 1000.0),
+					Field::Time => clf_time(SystemTime::now()),
+					Field::Referer => header(&log.referer),
+					Field::UserAgent => header(&log.user_agent),
+					Field::Note => log.note.clone(),
+				};
+			}
+			line
+		}
+	}
+
+	/// `time` in the format of the Common Log Format (e.g. `10/Oct/2000:13:55:36 +0000`)
+	fn clf_time(time: SystemTime) -> String {
+		// e.g. `Tue, 10 Oct 2000 13:55:36 GMT`
+		let date = httpdate::fmt_http_date(time);
+		let parts: Vec<_> = date.split(' ').collect();
+		match parts[..] {
+			[_, day, month, year, time, _] => format!("{day}/{month}/{year}:{time} +0000"),
+			_ => date,
+		}
+	}
+
+	/// log a handled request (if `level` is enabled)
+	pub fn request(level: LogLevel, log: &RequestLog) {
+		if !enabled(level) {
+			return;
+		}
+		let RequestLog {
+			client,
+			method,
+			uri,
+			scheme,
+			status,
+			bytes,
+			duration,
+			note,
+			..
+		} = log;
+		// this is to stdout, since what it does with requests *does* count as the output of the program!
+		if JSON.load(Ordering::Relaxed) {
+			let line = json!({
+				"timestamp": timestamp(),
+				"client": client.map(|c| c.to_string()),
+				"method": method.to_string(),
+				"uri": uri.to_string(),
+				"scheme": scheme,
+				"status": status,
+				"bytes": bytes,
+				"duration_ms": duration.as_secs_f64() * 1000.0,
+				"message": note,
+			});
+			emit(&REQUESTS_FILE, std::io::stdout(), line, true);
+		} else if let Some(template) = &*TEMPLATE.read().unwrap_or_else(|e| e.into_inner()) {
+			// templates contain their own timestamp if they want one
+			emit(
+				&REQUESTS_FILE,
+				std::io::stdout(),
+				template.format(log),
+				false,
+			);
+		} else {
+			let from = match client {
+				Some(client) => format!(" from {client}"),
+				None => String::new(),
+			};
+			emit(
+				&REQUESTS_FILE,
+				std::io::stdout(),
+				format_args!(
+					"[{method} {uri}{from}] {status}, {bytes} bytes, {duration:.1?}: {note}"
+				),
+				true,
+			);
+		}
+	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::*;
+
+		/// The log of a GET request to `uri`
+		fn request_log(uri: &str) -> RequestLog {
+			let request = Request::get(uri).body(()).unwrap();
+			RequestLog::new(&request, Some([10, 0, 0, 1].into()), "https")
+		}
+
+		#[test]
+		fn scheme_in_template() {
+			let template: Template = "{ip} {scheme} {uri}".parse().unwrap();
+			let log = request_log("/a?b");
+			assert_eq!(template.format(&log), "10.0.0.1 https /a?b");
+		}
+	}
+}
+
+pub mod cli {
+	use std::ffi::OsString;
+	use std::path::PathBuf;
+
+	use super::log::{CliMessages, LogLevel};
+
+	#[derive(Debug, Clone, Eq, PartialEq)]
+	pub struct Args {
+		pub config: PathBuf,
+		pub mode: Mode,
+		/// Overrides the port of the config's `addr`
+		pub port: Option<u16>,
+		/// Overrides the config's `log_level`
+		pub log_level: Option<LogLevel>,
+		/// Whether to log all routes at startup
+		pub list_routes: bool,
+		/// Whether to watch the served files for changes
+		pub watch: bool,
+		/// Whether to watch the served files and reload HTML pages in the browser when they change
+		pub dev: bool,
+	}
+
+	#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+	pub enum Mode {
+		/// Run the server
+		Serve,
+		/// Only load the config file to check it
+		Check,
+		/// Only load the config file and print how it was processed
+		DumpConfig,
+	}
+
+	pub const PRINT_README_FLAG: &str = "print-readme";
+	pub const CHECK_FLAG: &str = "check";
+	pub const DUMP_CONFIG_FLAG: &str = "dump-config";
+	pub const LIST_ROUTES_FLAG: &str = "list-routes";
+	pub const WATCH_FLAG: &str = "watch";
+	pub const DEV_FLAG: &str = "dev";
+	pub const PORT_FLAG: &str = "port";
+	pub const QUIET_FLAG: &str = "quiet";
+	pub const VERBOSE_FLAG: &str = "verbose";
+
+	pub fn parse_env() -> Args {
+		let mut args = std::env::args_os();
+		let msg = CliMessages(args.next().map(|s| s.to_string_lossy().to_string()));
+
+		let Some(parsed) = args
+			.next()
+			.and_then(|arg| process_options(&msg, arg, &mut args))
+		else {
+			msg.err_missing_config()
+		};
+		if args.count() > 0 {
+			msg.err("too many arguments")
+		}
+
+		parsed
+	}
+
+	fn free_arg(config: OsString) -> Args {
+		Args {
+			config: config.into(),
+			mode: Mode::Serve,
+			port: None,
+			log_level: None,
+			list_routes: false,
+			watch: false,
+			dev: false,
+		}
+	}
+
+	fn parse_port(msg: &CliMessages, value: Option<OsString>) -> u16 {
+		let Some(value) = value else {
+			msg.err("missing value for the port option")
+		};
+		match value.to_str().and_then(|v| v.parse().ok()) {
+			Some(port) => port,
+			None => msg.err(format_args!("invalid port {value:?}")),
+		}
+	}
+
+	fn process_options(
+		msg: &CliMessages,
+		arg: OsString,
+		rest: &mut impl Iterator<Item = OsString>,
+	) -> Option<Args> {
+		match arg
+			.to_string_lossy()
+			.strip_prefix('-')
+			.map(|s| s.strip_prefix('-').ok_or(s))
+		{
+			None => {
+				// free arg
+				Some(free_arg(arg))
+			}
+			Some(Err(s)) => {
+				// single `-` => option
+				match s {
+					"h" => msg.print_help(),
+					"p" => process_flag(msg, PORT_FLAG, rest),
+					"q" => process_flag(msg, QUIET_FLAG, rest),
+					"v" => process_flag(msg, VERBOSE_FLAG, rest),
+					opt => msg.err_invalid(opt, false),
+				}
+			}
+			Some(Ok(s)) => {
+				// double `-` => flag
+				match s {
+					// empty means just `--`.
+					// This marks the end of any arg parsing, so the config file may start with a `-`
+					"" => rest.next().map(free_arg),
+					"help" => msg.print_help(),
+					PRINT_README_FLAG => super::log::print_readme(),
+					CHECK_FLAG | DUMP_CONFIG_FLAG | LIST_ROUTES_FLAG | PORT_FLAG | QUIET_FLAG
+					| VERBOSE_FLAG | WATCH_FLAG | DEV_FLAG => process_flag(msg, s, rest),
+					flag => msg.err_invalid(flag, true),
+				}
+			}
+		}
+	}
+
+	/// Process a flag that modifies the args parsed from the rest of the command line
+	fn process_flag(
+		msg: &CliMessages,
+		flag: &str,
+		rest: &mut impl Iterator<Item = OsString>,
+	) -> Option<Args> {
+		let port = match flag {
+			PORT_FLAG => Some(parse_port(msg, rest.next())),
+			_ => None,
+		};
+		let args = process_options(msg, rest.next()?, rest)?;
+		Some(match flag {
+			CHECK_FLAG => Args {
+				mode: Mode::Check,
+				..args
+			},
+			DUMP_CONFIG_FLAG => Args {
+				mode: Mode::DumpConfig,
+				..args
+			},
+			LIST_ROUTES_FLAG => Args {
+				list_routes: true,
+				..args
+			},
+			PORT_FLAG => Args { port, ..args },
+			QUIET_FLAG => Args {
+				log_level: Some(LogLevel::Error),
+				..args
+			},
+			VERBOSE_FLAG => Args {
+				log_level: Some(LogLevel::Debug),
+				..args
+			},
+			WATCH_FLAG => Args {
+				watch: true,
+				..args
+			},
+			DEV_FLAG => Args { dev: true, ..args },
+			_ => unreachable!("{flag:?} is not a modifying flag"),
+		})
+	}
+}
+
+mod embedded {
+	use std::path::{Component, Path};
+
+	// defines `static FILES: &[(&str, &[u8])]`, sorted by name
+	include!(concat!(env!("OUT_DIR"), "/embedded.rs"));
+
+	/// The name of the embedded file at `path`, i.e. the path with `/` as the separator
+	fn name(path: &Path) -> Option<String> {
+		let mut parts = vec![];
+		for component in path.components() {
+			match component {
+				Component::Normal(p) => parts.push(p.to_str()?),
+				Component::CurDir => {}
+				_ => return None,
+			}
+		}
+		Some(parts.join("/"))
+	}
+
+	/// The content of the embedded file at `path`
+	pub fn get(path: &Path) -> Option<&'static [u8]> {
+		let name = name(path)?;
+		let i = FILES
+			.binary_search_by_key(&name.as_str(), |&(n, _)| n)
+			.ok()?;
+		Some(FILES[i].1)
+	}
+
+	/// Whether `path` is a directory that contains embedded files
+	pub fn is_dir(path: &Path) -> bool {
+		let Some(name) = name(path) else {
+			return false;
+		};
+		FILES.iter().any(|(n, _)| {
+			name.is_empty()
+				|| n.strip_prefix(name.as_str())
+					.is_some_and(|rest| rest.starts_with('/'))
+		})
+	}
+
+	/// The names of the embedded files that match `pattern`
+	pub fn glob(pattern: &glob::Pattern) -> impl Iterator<Item = &'static str> + '_ {
+		let options = glob::MatchOptions {
+			require_literal_separator: true,
+			..Default::default()
+		};
+		FILES
+			.iter()
+			.map(|&(n, _)| n)
+			.filter(move |n| pattern.matches_with(n, options))
+	}
+}
+
+pub mod config {
+	use std::borrow::Cow;
+	use std::collections::HashMap;
+	use std::fmt::Display;
+	use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
+	use std::ops::{Deref, DerefMut};
+	use std::path::{Path, PathBuf};
+	use std::str::FromStr;
+
+	use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+	use camino::{Utf8Path, Utf8PathBuf};
+	use mime::Mime;
+	use percent_encoding::percent_decode_str;
+	use serde::Deserialize;
+
+	use super::embedded;
+	use super::log::{self, LogFormat, LogLevel};
+
+	#[derive(Debug, Clone)]
+	enum HybridPathBuf {
+		Utf8(Utf8PathBuf),
+		NonUtf8(PathBuf),
+	}
+
+	impl HybridPathBuf {
+		pub fn from_std_path_buf(path: PathBuf) -> Self {
+			match Utf8PathBuf::from_path_buf(path) {
+				Ok(p) => Self::Utf8(p),
+				Err(p) => Self::NonUtf8(p),
+			}
+		}
+
+		pub fn is_absolute(&self) -> bool {
+			match self {
+				Self::Utf8(p) => p.is_absolute(),
+				Self::NonUtf8(p) => p.is_absolute(),
+			}
+		}
+	}
+
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	#[serde(untagged)]
+	pub enum FileObject {
+		InferMime(Utf8PathBuf),
+		Map {
+			/// The MIME type. If absent, it is inferred like for `InferMime`.
+			r#type: Option<String>,
+			path: Utf8PathBuf,
+			cache_control: Option<String>,
+			/// Additional headers for successful responses
+			#[serde(default)]
+			headers: HashMap<String, String>,
+			/// Whether authentication is required (if absent, `auth.all_routes` decides)
+			auth_required: Option<bool>,
+			/// Whether the file is offered as a download instead of being displayed
+			#[serde(default)]
+			download: bool,
+			/// The file name for downloads (implies `download`)
+			filename: Option<String>,
+			/// Whether `{{var}}` placeholders in the file are replaced with the `template_vars`
+			#[serde(default)]
+			template: bool,
+		},
+	}
+
+	/// The configured MIME types
+	#[derive(Debug, Clone, Default, Eq, PartialEq)]
+	pub struct MimeTypes {
+		/// The MIME types by file extension (without the leading `.`)
+		pub extensions: HashMap<String, Mime>,
+		/// The MIME type of files whose type can't be inferred from their extension
+		pub default: Option<Mime>,
+	}
+
+	/// Infer the MIME type from the extension of `path`,
+	/// preferring the configured `mime_types` over the built-in ones
+	/// and falling back to the `default_mime`
+	fn infer_mime(path: &Utf8Path, mime_types: &MimeTypes) -> Option<Mime> {
+		let inferred = path.extension().and_then(|extension| {
+			let extension = extension.to_ascii_lowercase();
+			match mime_types.extensions.get(&extension) {
+				Some(mime) => Some(mime.clone()),
+				None => builtin_mime(&extension),
+			}
+		});
+		inferred.or_else(|| mime_types.default.clone())
+	}
+
+	/// Whether `url` is `path` (if given), ignoring leading slashes
+	fn is_path(path: Option<&str>, url: &str) -> bool {
+		let url = url.strip_prefix('/').unwrap_or(url);
+		path.is_some_and(|p| p.strip_prefix('/').unwrap_or(p) == url)
+	}
+
+	/// The built-in MIME type for files with `extension`
+	/// (text types are declared as UTF-8)
+	fn builtin_mime(extension: &str) -> Option<Mime> {
+		Some(match extension {
+			"txt" => mime::TEXT_PLAIN_UTF_8,
+			"html" | "htm" => mime::TEXT_HTML_UTF_8,
+			"css" => mime::TEXT_CSS_UTF_8,
+			"csv" => mime::TEXT_CSV_UTF_8,
+			"md" => Mime::from_str("text/markdown; charset=utf-8").ok()?,
+			"js" => Mime::from_str("text/javascript; charset=utf-8").ok()?,
+			"png" => mime::IMAGE_PNG,
+			"jpg" | "jpeg" => mime::IMAGE_JPEG,
+			"webp" => Mime::from_str("image/webp").ok()?,
+			"jxl" => Mime::from_str("image/jxl").ok()?,
+			"avif" => Mime::from_str("image/avif").ok()?,
+			"gif" => mime::IMAGE_GIF,
+			"svg" => mime::IMAGE_SVG,
+			"ico" => Mime::from_str("image/x-icon").ok()?,
+			"mp4" => Mime::from_str("video/mp4").ok()?,
+			// not an official mime type but the suggested one by matroska.org
+			"mkv" => Mime::from_str("video/x-matroska").ok()?,
+			"webm" => Mime::from_str("video/webm").ok()?,
+			"mp3" => Mime::from_str("audio/mpeg").ok()?,
+			"ogg" | "opus" => Mime::from_str("audio/ogg").ok()?,
+			"weba" => Mime::from_str("audio/webm").ok()?,
+			"wav" => Mime::from_str("audio/wav").ok()?,
+			"xml" => Mime::from_str("application/xml").ok()?,
+			"json" => mime::APPLICATION_JSON,
+			"pdf" => mime::APPLICATION_PDF,
+			"wasm" => Mime::from_str("application/wasm").ok()?,
+			"ttf" => Mime::from_str("font/ttf").ok()?,
+			"otf" => Mime::from_str("font/otf").ok()?,
+			"woff" => mime::FONT_WOFF,
+			"woff2" => mime::FONT_WOFF2,
+			_ => return None,
+		})
+	}
+
+	impl FileObject {
+		pub fn path(&self) -> &Utf8PathBuf {
+			match self {
+				FileObject::InferMime(p) => p,
+				FileObject::Map { path, .. } => path,
+			}
+		}
+
+		pub fn path_mut(&mut self) -> &mut Utf8PathBuf {
+			match self {
+				FileObject::InferMime(p) => p,
+				FileObject::Map { path, .. } => path,
+			}
+		}
+
+		pub fn into_path(self) -> Utf8PathBuf {
+			match self {
+				FileObject::InferMime(p) => p,
+				FileObject::Map { path, .. } => path,
+			}
+		}
+
+		/// The MIME type if it was given explicitly
+		fn explicit_mime(&self) -> Option<Mime> {
+			match self {
+				FileObject::InferMime(_) => None,
+				FileObject::Map { r#type, .. } => Mime::from_str(r#type.as_ref()?).ok(),
+			}
+		}
+
+		/// Process this into a `Route`, resolving the path relative to `root`.
+		fn into_route(self, root: &Path, mime_types: &MimeTypes) -> Route {
+			match self {
+				FileObject::InferMime(path) => {
+					let (source, file_path) = resolve_file_path(root, &path);
+					Route {
+						mime: infer_mime(&path, mime_types),
+						path: file_path,
+						source,
+						cache_control: None,
+						headers: HeaderMap::new(),
+						dir_index: false,
+						auth_required: None,
+						download: false,
+						filename: None,
+						template: false,
+						alternatives: vec![],
+						pattern: String::new(),
+					}
+				}
+				FileObject::Map {
+					r#type,
+					path,
+					cache_control,
+					headers,
+					auth_required,
+					download,
+					filename,
+					template,
+				} => {
+					let mime = r#type
+						.and_then(|t| {
+							Mime::from_str(&t)
+								.map_err(|e| {
+									log::warn(format_args!(
+										"ignoring invalid `type` value {t:?} for {path:?} ({e})"
+									));
+								})
+								.ok()
+						})
+						.or_else(|| infer_mime(&path, mime_types));
+					let cache_control = cache_control.and_then(|c| {
+						HeaderValue::from_str(&c)
+							.map_err(|_| {
+								log::warn(format_args!(
+									"ignoring invalid `cache_control` value {c:?} for {path:?}"
+								));
+							})
+							.ok()
+					});
+					let headers = parse_headers(headers, format_args!("for {path:?}"));
+					let (source, file_path) = resolve_file_path(root, &path);
+					Route {
+						mime,
+						path: file_path,
+						source,
+						cache_control,
+						headers,
+						dir_index: false,
+						auth_required,
+						download: download || filename.is_some(),
+						filename,
+						template,
+						alternatives: vec![],
+						pattern: String::new(),
+					}
+				}
+			}
+		}
+	}
+
+	/// The content source of the FileObject path `path` and the path itself,
+	/// resolved relative to `root` if it's on disk.
+	fn resolve_file_path(root: &Path, path: &Utf8Path) -> (ContentSource, PathBuf) {
+		match path.as_str().strip_prefix(EMBEDDED_PREFIX) {
+			Some(name) => (ContentSource::Embedded, PathBuf::from(name)),
+			None => (ContentSource::Disk, root.join(path.as_std_path())),
+		}
+	}
+
+	/// The URL of the `direct` route to `path` (without `embedded:`)
+	fn direct_url(path: &Utf8Path) -> String {
+		let path = path.as_str();
+		path.strip_prefix(EMBEDDED_PREFIX)
+			.unwrap_or(path)
+			.to_string()
+	}
+
+	/// Parse the header names and values, ignoring (and warning about) invalid ones.
+	///
+	/// `context` is appended to the warnings.
+	fn parse_headers(headers: HashMap<String, String>, context: impl Display) -> HeaderMap {
+		headers
+			.into_iter()
+			.filter_map(|(name, value)| {
+				let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+					log::warn(format_args!(
+						"ignoring invalid header name {name:?} {context}"
+					));
+					return None;
+				};
+				let Ok(value) = HeaderValue::from_str(&value) else {
+					log::warn(format_args!(
+						"ignoring invalid value {value:?} of header {name} {context}"
+					));
+					return None;
+				};
+				Some((name, value))
+			})
+			.collect()
+	}
+
+	/// Expand environment variables in all strings of `value` (see [`expand_env`])
+	fn expand_env_vars(value: &mut toml::Value) -> Result<(), ConfigError> {
+		match value {
+			toml::Value::String(s) => *s = expand_env(s)?,
+			toml::Value::Array(a) => a.iter_mut().try_for_each(expand_env_vars)?,
+			toml::Value::Table(t) => t.iter_mut().try_for_each(|(_, v)| expand_env_vars(v))?,
+			_ => {}
+		}
+		Ok(())
+	}
+
+	/// The paths (relative to `root`) of the files that match the glob `pattern`
+	fn expand_glob(root: &Path, pattern: &Utf8Path) -> Vec<Utf8PathBuf> {
+		if let Some(embedded_pattern) = pattern.as_str().strip_prefix(EMBEDDED_PREFIX) {
+			return match glob::Pattern::new(embedded_pattern) {
+				Ok(p) => embedded::glob(&p)
+					.map(|name| Utf8PathBuf::from(format!("{EMBEDDED_PREFIX}{name}")))
+					.collect(),
+				Err(e) => {
+					log::warn(format_args!("ignoring invalid pattern {pattern:?} ({e})"));
+					vec![]
+				}
+			};
+		}
+		let Some(root_str) = root.to_str() else {
+			log::warn(format_args!(
+				"can't expand {pattern:?} (the config file's directory isn't valid UTF-8)"
+			));
+			return vec![];
+		};
+		let full_pattern = format!("{}/{pattern}", glob::Pattern::escape(root_str));
+		let paths = match glob::glob(&full_pattern) {
+			Ok(paths) => paths,
+			Err(e) => {
+				log::warn(format_args!("ignoring invalid pattern {pattern:?} ({e})"));
+				return vec![];
+			}
+		};
+		let mut files: Vec<_> = paths
+			.filter_map(|p| p.ok())
+			.filter(|p| p.is_file())
+			.filter_map(|p| {
+				let rel = p.strip_prefix(root).ok()?;
+				Utf8PathBuf::from_path_buf(rel.to_path_buf()).ok()
+			})
+			.collect();
+		files.sort();
+		files
+	}
+
+	/// Merge `other` into `base`, with the values of `other` taking priority.
+	///
+	/// Tables are merged recursively, everything else (including arrays) is replaced.
+	fn merge_toml(base: &mut toml::Value, other: toml::Value) {
+		match (base, other) {
+			(toml::Value::Table(base), toml::Value::Table(other)) => {
+				for (key, value) in other {
+					match base.get_mut(&key) {
+						Some(base) => merge_toml(base, value),
+						None => {
+							base.insert(key, value);
+						}
+					}
+				}
+			}
+			(base, other) => *base = other,
+		}
+	}
+
+	/// Replace every `${VAR}` in `s` with the value of the environment variable `VAR`.
+	///
+	/// `$${` produces a literal `${`.
+	fn expand_env(s: &str) -> Result<String, ConfigError> {
+		let mut out = String::with_capacity(s.len());
+		let mut rest = s;
+		while let Some(i) = rest.find("${") {
+			let (before, after) = (&rest[..i], &rest[i + 2..]);
+			if let Some(before) = before.strip_suffix('$') {
+				out.push_str(before);
+				out.push_str("${");
+				rest = after;
+				continue;
+			}
+			out.push_str(before);
+			let Some(end) = after.find('}') else {
+				return Err(ConfigError::UnterminatedEnvVar(s.to_string()));
+			};
+			let name = &after[..end];
+			let value = std::env::var(name).map_err(|error| ConfigError::EnvVar {
+				name: name.to_string(),
+				value: s.to_string(),
+				error,
+			})?;
+			out.push_str(&value);
+			rest = &after[end + 1..];
+		}
+		out.push_str(rest);
+		Ok(out)
+	}
+
+	/// The prefix marking an address as the path of a unix domain socket
+	pub const UNIX_PREFIX: &str = "unix:";
+
+	/// The prefix marking a file path as the name of an embedded file
+	pub const EMBEDDED_PREFIX: &str = "embedded:";
+
+	/// The file that is served for requests to a directory
+	pub const INDEX_FILE: &str = "index.html";
+
+	/// Where the content of a route comes from
+	#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+	pub enum ContentSource {
+		/// The file system
+		Disk,
+		/// The files that were embedded into the binary at build time
+		Embedded,
+	}
+
+	/// A processed GET route
+	#[derive(Debug, Clone, Eq, PartialEq)]
+	pub struct Route {
+		pub mime: Option<Mime>,
+		/// The absolute path of the file,
+		/// or its name relative to the embedded directory if `source` is `Embedded`
+		pub path: PathBuf,
+		pub source: ContentSource,
+		/// The value of the `Cache-Control` header for successful responses
+		pub cache_control: Option<HeaderValue>,
+		/// Additional headers for successful responses
+		pub headers: HeaderMap,
+		/// Whether this is the index file of a directory route
+		/// (so that a listing can be generated if it doesn't exist)
+		pub dir_index: bool,
+		/// Whether authentication is required (if unset, `auth.all_routes` decides)
+		pub auth_required: Option<bool>,
+		/// Whether successful responses are downloads (`Content-Disposition: attachment`)
+		pub download: bool,
+		/// The file name for downloads (if unset, the name of the file is used)
+		pub filename: Option<String>,
+		/// Whether `{{var}}` placeholders are replaced with the `template_vars` (in text files)
+		pub template: bool,
+		/// The representations that requests are served depending on their `Accept` header (if any).
+		///
+		/// If there are some, the other fields are those of the first one.
+		pub alternatives: Vec<Route>,
+		/// The URL pattern this was configured under (e.g. `/static/*`, with a leading slash),
+		/// or an empty string for routes that aren't part of the route table (like the `fallback`)
+		pub pattern: String,
+	}
+
+	impl Route {
+		/// A human-readable description of the file (relative to `root`) and MIME type.
+		///
+		/// `no_mime` describes a missing MIME type.
+		fn describe(&self, root: &Path, no_mime: &str) -> String {
+			if !self.alternatives.is_empty() {
+				let alternatives: Vec<_> = (self.alternatives.iter())
+					.map(|a| a.describe(root, no_mime))
+					.collect();
+				return alternatives.join(" or ");
+			}
+			let source = match self.source {
+				ContentSource::Disk => "",
+				ContentSource::Embedded => "embedded ",
+			};
+			let mime = match &self.mime {
+				Some(mime) => mime.to_string(),
+				None => no_mime.to_string(),
+			};
+			let path = self.path.strip_prefix(root).unwrap_or(&self.path);
+			format!("{source}{path:?} ({mime})")
+		}
+
+		/// The routes whose files this route serves, i.e. its alternatives or itself
+		pub fn files(&self) -> impl Iterator<Item = &Route> {
+			let own = match self.alternatives.is_empty() {
+				true => Some(self),
+				false => None,
+			};
+			own.into_iter().chain(&self.alternatives)
+		}
+	}
+
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	pub struct Unspecial {
+		pub unspecial: Option<FileObject>,
+		pub direct: Option<FileObject>,
+		pub dirs: Option<FileObject>,
+	}
+
+	impl Unspecial {
+		fn into_kv_iter(self) -> impl Iterator<Item = (String, FileObject)> {
+			[
+				("unspecial", self.unspecial),
+				("direct", self.direct),
+				("dirs", self.dirs),
+			]
+			.into_iter()
+			.filter_map(|(k, f)| Some((k.to_string(), f?)))
+		}
+	}
+
+	#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+	pub struct GetRoutes {
+		#[serde(default)]
+		pub direct: Vec<FileObject>,
+		pub unspecial: Option<Unspecial>,
+		/// URL prefixes that map to directories
+		#[serde(default)]
+		pub dirs: HashMap<String, FileObject>,
+		#[serde(flatten)]
+		pub map: HashMap<String, RouteValue>,
+	}
+
+	/// The value of a key of `get_routes`
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	#[serde(untagged)]
+	pub enum RouteValue {
+		File(FileObject),
+		/// Representations that are chosen between by the `Accept` header of requests
+		Alternatives(Vec<FileObject>),
+	}
+
+	struct RelativizeReport {
+		/// `direct` paths that weren't descendants of the root path
+		parent: Vec<Utf8PathBuf>,
+		/// `direct` paths that were converted to relative paths
+		made_to_rel: Vec<(Utf8PathBuf, Utf8PathBuf)>,
+	}
+
+	impl GetRoutes {
+		fn relativize_direct_routes(&mut self, root: &HybridPathBuf) -> RelativizeReport {
+			debug_assert!(root.is_absolute());
+
+			let made_to_rel = self
+				.direct
+				.iter_mut()
+				.filter_map(|f| {
+					let HybridPathBuf::Utf8(root) = root else {
+						// Since `root` isn't UTF-8, `r.path` is guaranteed to not be a descendant of `root`
+						return None;
+					};
+					f.path()
+						.strip_prefix(root)
+						.ok()
+						.map(|rel| rel.to_path_buf())
+						.map(|rel| {
+							let abs = std::mem::replace(f.path_mut(), rel.clone());
+							(abs, rel)
+						})
+				})
+				.collect();
+
+			let mut parent = vec![];
+			let mut kept_direct = vec![];
+			for f in self.direct.drain(..) {
+				if f.path().is_absolute() {
+					// all paths that are now still absolute failed to be made relative
+					parent.push(f.into_path());
+				} else {
+					kept_direct.push(f);
+				}
+			}
+			self.direct = kept_direct;
+
+			RelativizeReport {
+				parent,
+				made_to_rel,
+			}
+		}
+	}
+
+	#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+	pub struct SocketConfig {
+		/// The maximum number of pending connections (default 1024)
+		pub backlog: Option<u32>,
+		/// Whether `SO_REUSEADDR` is set (by default only on unix)
+		pub reuse_addr: Option<bool>,
+		/// Whether `TCP_NODELAY` is set for accepted connections
+		pub nodelay: Option<bool>,
+		/// Whether IPv6 sockets only accept IPv6 connections (by default, the OS decides)
+		pub ipv6_only: Option<bool>,
+		/// How often a failed bind is retried (default 0)
+		#[serde(default)]
+		pub bind_retries: u32,
+		/// The delay (in milliseconds) before the first retry, which doubles for every further retry
+		pub bind_retry_delay_ms: Option<u64>,
+	}
+
+	/// The value of `default_favicon`
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	#[serde(untagged)]
+	pub enum DefaultFavicon {
+		/// Whether to use the built-in (transparent) icon
+		Builtin(bool),
+		File(FileObject),
+	}
+
+	/// The value of `server_header`
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	#[serde(untagged)]
+	pub enum ServerHeader {
+		/// Whether to send `simple-http-server/<version>` (`false` removes the header entirely)
+		Enabled(bool),
+		Value(String),
+	}
+
+	/// The processed `default_favicon`
+	#[derive(Debug, Clone, Eq, PartialEq)]
+	pub enum Favicon {
+		Builtin,
+		File(Box<Route>),
+	}
+
+	/// The value of `addr`
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	#[serde(untagged)]
+	pub enum Addrs {
+		/// A single address, with `failsafe_addrs` as its alternatives
+		One(String),
+		/// Several addresses that are all listened on
+		Many(Vec<AddrGroup>),
+	}
+
+	/// An entry of a list of addresses
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	#[serde(untagged)]
+	pub enum AddrGroup {
+		One(String),
+		/// Alternatives of which the first that works is used
+		Failsafe(Vec<String>),
+	}
+
+	impl AddrGroup {
+		pub fn addrs(&self) -> Vec<String> {
+			match self {
+				Self::One(addr) => vec![addr.clone()],
+				Self::Failsafe(addrs) => addrs.clone(),
+			}
+		}
+	}
+
+	/// `addr` with its port replaced by `port`
+	fn override_port(addr: &str, port: u16) -> Result<String, ConfigError> {
+		if addr.starts_with(UNIX_PREFIX) {
+			return Err(ConfigError::UnixPortOverride);
+		}
+		let Some((host, _)) = addr.rsplit_once(':') else {
+			return Err(ConfigError::NoPort(addr.to_string()));
+		};
+		Ok(format!("{host}:{port}"))
+	}
+
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	pub struct BindConfig {
+		pub host: String,
+		pub port: u16,
+	}
+
+	impl BindConfig {
+		/// The address in the format of `addr`
+		pub fn addr(&self) -> String {
+			// IPv6 addresses need brackets to separate them from the port
+			if self.host.contains(':') && !self.host.starts_with('[') {
+				format!("[{}]:{}", self.host, self.port)
+			} else {
+				format!("{}:{}", self.host, self.port)
+			}
+		}
+	}
+
+	#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+	pub struct HttpConfig {
+		/// Whether HTTP/1 connections are kept alive after a request (default `true`)
+		pub keep_alive: Option<bool>,
+		/// The time (in seconds) after which idle HTTP/1 connections are closed (by default, never)
+		pub keep_alive_timeout_secs: Option<u64>,
+		/// The number of requests after which HTTP/1 connections are closed (by default, unlimited)
+		pub max_requests_per_connection: Option<NonZeroU64>,
+		/// The number of simultaneous connections, beyond which new connections wait
+		/// until one is closed (by default, unlimited)
+		pub max_connections: Option<NonZeroUsize>,
+	}
+
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	pub struct TlsConfig {
+		/// The path of the certificate chain (in PEM format)
+		pub cert: Utf8PathBuf,
+		/// The path of the private key (in PEM format)
+		pub key: Utf8PathBuf,
+		/// The address of an additional plain HTTP listener that redirects everything to HTTPS
+		pub redirect_http_from: Option<String>,
+	}
+
+	/// A content encoding for compressed responses
+	#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+	pub enum Encoding {
+		#[serde(rename = "br")]
+		Brotli,
+		#[serde(rename = "gzip")]
+		Gzip,
+	}
+
+	impl Encoding {
+		/// The name in the `Accept-Encoding` and `Content-Encoding` headers
+		pub fn name(self) -> &'static str {
+			match self {
+				Encoding::Brotli => "br",
+				Encoding::Gzip => "gzip",
+			}
+		}
+	}
+
+	/// How requests that only differ from a route by a trailing slash are handled
+	#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+	#[serde(rename_all = "lowercase")]
+	pub enum TrailingSlash {
+		/// Such requests don't match
+		#[default]
+		Strict,
+		/// Such requests match the route
+		Ignore,
+		/// Such requests are redirected to the route
+		Redirect,
+	}
+
+	#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+	#[serde(rename_all = "lowercase")]
+	pub enum AuthMode {
+		/// HTTP basic authentication with a username and password
+		#[default]
+		Basic,
+		/// A static bearer token
+		Bearer,
+	}
+
+	impl AuthMode {
+		/// The scheme in the `Authorization` header
+		pub fn scheme(self) -> &'static str {
+			match self {
+				AuthMode::Basic => "Basic",
+				AuthMode::Bearer => "Bearer",
+			}
+		}
+	}
+
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	pub struct AuthConfig {
+		/// How requests are authenticated
+		#[serde(default)]
+		pub mode: AuthMode,
+		/// The realm that is sent to clients when asking for credentials
+		pub realm: Option<String>,
+		/// The usernames and passwords for basic authentication
+		#[serde(default)]
+		pub users: HashMap<String, String>,
+		/// The token for bearer authentication
+		pub token: Option<String>,
+		/// Whether routes require authentication unless they set `auth_required = false`
+		#[serde(default)]
+		pub all_routes: bool,
+	}
+
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	pub struct CorsConfig {
+		/// The origins that may access the server (`"*"` allows all)
+		pub allowed_origins: Vec<String>,
+		/// The methods that may be used in cross-origin requests
+		#[serde(default = "CorsConfig::default_methods")]
+		pub allowed_methods: Vec<String>,
+		/// The request headers that may be used in cross-origin requests
+		#[serde(default)]
+		pub allowed_headers: Vec<String>,
+		/// How long (in seconds) clients may cache the result of a preflight request
+		pub max_age: Option<u64>,
+	}
+
+	impl CorsConfig {
+		fn default_methods() -> Vec<String> {
+			["GET", "HEAD", "OPTIONS"].map(String::from).to_vec()
+		}
+	}
+
+	#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+	pub struct RateLimitConfig {
+		/// How many requests per second each client may make on average
+		pub requests_per_second: NonZeroU32,
+		/// How many requests each client may make at once (default `requests_per_second`)
+		pub burst: Option<NonZeroU32>,
+	}
+
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	pub struct RedirectConfig {
+		/// The URL or server-relative path to redirect to
+		pub to: String,
+		/// Whether the redirect is permanent (301) instead of temporary (302)
+		#[serde(default)]
+		pub permanent: bool,
+	}
+
+	/// A `rewrite` section
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	pub struct RewriteConfig {
+		/// The URL pattern, where a segment of the form `:name` matches any (non-empty) segment
+		pub from: String,
+		/// The URL that matching requests are routed as, with every `:name` replaced by what it matched
+		pub to: String,
+	}
+
+	/// A processed rewrite rule
+	#[derive(Debug, Clone, Eq, PartialEq)]
+	pub struct Rewrite {
+		/// The pattern (without a leading slash)
+		pub from: String,
+		/// The template of the rewritten URL
+		pub to: String,
+	}
+
+	impl Rewrite {
+		fn new(r: &RewriteConfig) -> Result<Self, ConfigError> {
+			let from = r.from.strip_prefix('/').unwrap_or(&r.from);
+			let captures: Vec<_> = from.split('/').filter_map(capture_name).collect();
+			let unknown = placeholders(&r.to).any(|(_, name)| !captures.contains(&name));
+			if unknown {
+				return Err(ConfigError::InvalidRewrite {
+					from: r.from.clone(),
+					to: r.to.clone(),
+				});
+			}
+			Ok(Self {
+				from: from.to_string(),
+				to: r.to.clone(),
+			})
+		}
+
+		/// The rewritten URL if the normalized `url` matches
+		fn apply(&self, url: &str) -> Option<String> {
+			let mut captures = HashMap::new();
+			let mut segments = url.split('/');
+			for pattern in self.from.split('/') {
+				let segment = segments.next()?;
+				match capture_name(pattern) {
+					Some(name) if !segment.is_empty() => {
+						captures.insert(name, segment);
+					}
+					_ if pattern == segment => {}
+					_ => return None,
+				}
+			}
+			if segments.next().is_some() {
+				return None;
+			}
+			let mut out = String::new();
+			let mut rest = self.to.as_str();
+			for (start, name) in placeholders(&self.to) {
+				let offset = self.to.len() - rest.len();
+				out.push_str(&rest[..start - offset]);
+				// the rewritten URL is percent-decoded again when it is routed
+				out.push_str(&captures[name].replace('%', "%25"));
+				rest = &self.to[start + 1 + name.len()..];
+			}
+			out.push_str(rest);
+			Some(out)
+		}
+	}
+
+	/// The name of the capture if `segment` (of a `rewrite` pattern) is one
+	fn capture_name(segment: &str) -> Option<&str> {
+		segment.strip_prefix(':').filter(|name| !name.is_empty())
+	}
+
+	/// The positions and names of the `:name` placeholders in `template`,
+	/// where names consist of ASCII alphanumerics and underscores
+	fn placeholders(template: &str) -> impl Iterator<Item = (usize, &str)> {
+		template.match_indices(':').filter_map(move |(i, _)| {
+			let rest = &template[i + 1..];
+			let len = rest
+				.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+				.unwrap_or(rest.len());
+			(len > 0).then(|| (i, &rest[..len]))
+		})
+	}
+
+	/// A processed redirect
+	#[derive(Debug, Clone, Eq, PartialEq)]
+	pub struct Redirect {
+		pub location: HeaderValue,
+		pub permanent: bool,
+	}
+
+	/// The processed `cors` section
+	#[derive(Debug, Clone, Eq, PartialEq)]
+	pub struct CorsPolicy {
+		/// The allowed origins, or `None` if all are allowed
+		pub origins: Option<Vec<HeaderValue>>,
+		pub methods: HeaderValue,
+		pub headers: Option<HeaderValue>,
+		pub max_age: Option<HeaderValue>,
+	}
+
+	impl CorsPolicy {
+		fn new(cors: &CorsConfig) -> Result<Self, ConfigError> {
+			let list = |key: &str, items: &[String]| {
+				let value = items.join(", ");
+				HeaderValue::from_str(&value).map_err(|_| ConfigError::InvalidValue {
+					key: format!("cors.{key}"),
+					value,
+				})
+			};
+			let origins = if cors.allowed_origins.iter().any(|o| o == "*") {
+				None
+			} else {
+				let origins = cors.allowed_origins.iter().map(|o| {
+					HeaderValue::from_str(o).map_err(|_| ConfigError::InvalidValue {
+						key: "cors.allowed_origins".to_string(),
+						value: o.clone(),
+					})
+				});
+				Some(origins.collect::<Result<_, _>>()?)
+			};
+			Ok(Self {
+				origins,
+				methods: list("allowed_methods", &cors.allowed_methods)?,
+				headers: match &cors.allowed_headers[..] {
+					[] => None,
+					headers => Some(list("allowed_headers", headers)?),
+				},
+				max_age: cors.max_age.map(HeaderValue::from),
+			})
+		}
+	}
+
+	/// The processed routes of a `get_routes` section (of the config or a `vhost`)
+	#[derive(Debug, Clone, Default, Eq, PartialEq)]
+	pub struct RouteTable {
+		/// The processed exact routes (without leading slashes)
+		pub exact: HashMap<String, Route>,
+		/// The processed directory routes (sorted by descending prefix length).
+		///
+		/// The paths of these routes are the directories,
+		/// and their MIME types are only set if they were given explicitly.
+		pub dirs: Vec<(String, Route)>,
+		/// The processed routes with a wildcard (sorted by descending prefix and suffix length)
+		pub globs: Vec<GlobRoute>,
+	}
+
+	impl RouteTable {
+		/// Process `gr`, whose files are relative to `root`
+		fn new(gr: &mut GetRoutes, root: &Path, mime_types: &MimeTypes) -> Self {
+			let mut get_routes = HashMap::new();
+			let mut globs = vec![];
+			let mut dirs = vec![];
+			let root_h = HybridPathBuf::from_std_path_buf(root.to_path_buf());
+			let RelativizeReport {
+				parent,
+				made_to_rel,
+			} = gr.relativize_direct_routes(&root_h);
+			for path in parent {
+				log::warn(format_args!(
+					"ignoring {path:?} (absolute paths in `direct` must be descendants of the config file's directory)",
+				));
+			}
+			for (abs, rel) in made_to_rel {
+				log::info(format_args!(
+					"converted {abs:?} to the relative path {rel:?}",
+				));
+			}
+
+			// where each route came from, for warning about routes that override others
+			let mut sources = HashMap::new();
+			let mut insert = |url: String, mut route: Route, source: String| {
+				route.pattern = format!("/{url}");
+				if let Some(old) = sources.insert(url.clone(), source.clone()) {
+					log::warn(format_args!(
+						"the route {url:?} from {source} overrides the one from {old}"
+					));
+				}
+				get_routes.insert(url, route);
+			};
+
+			// note: The originals aren't used after this, so draining should be fine here
+			let mut map: Vec<_> = gr.map.drain().collect();
+			// sorted so that it's deterministic which key wins if two are the same after normalization
+			map.sort_by(|(a, _), (b, _)| a.cmp(b));
+			let unspecial = gr
+				.unspecial
+				.take()
+				.into_iter()
+				.flat_map(|u| u.into_kv_iter());
+			let unspecial = unspecial.map(|(k, f)| (k, RouteValue::File(f)));
+			for (k, v) in map.into_iter().chain(unspecial) {
+				let source = format!("the key {k:?}");
+				// so that keys can be written with or without a leading slash
+				let k = k.strip_prefix('/').map(str::to_string).unwrap_or(k);
+				let f = match v {
+					RouteValue::File(f) if !f.path().is_relative() => continue,
+					RouteValue::File(f) => f,
+					RouteValue::Alternatives(files) => {
+						let alternatives: Vec<_> = (files.into_iter())
+							.filter(|f| f.path().is_relative())
+							.map(|f| f.into_route(root, mime_types))
+							.collect();
+						match alternatives.first() {
+							Some(first) => {
+								let route = Route {
+									alternatives: alternatives.clone(),
+									..first.clone()
+								};
+								insert(k, route, source);
+							}
+							None => log::warn(format_args!(
+								"ignoring the key {k:?} (it has no alternatives)"
+							)),
+						}
+						continue;
+					}
+				};
+				match GlobRoute::new(&k, f, root, mime_types) {
+					Ok(glob) => globs.push(glob),
+					Err(f) => insert(k, f.into_route(root, mime_types), source),
+				}
+			}
+			// note: the order matters here. Handling `direct` after `map` means that `direct` takes priority
+			for f in gr.direct.drain(..) {
+				if !f.path().is_relative() {
+					continue;
+				}
+				let source = format!("the `direct` entry {:?}", f.path());
+				if !f.path().as_str().contains(['*', '?', '[']) {
+					insert(direct_url(f.path()), f.into_route(root, mime_types), source);
+					continue;
+				}
+				let files = expand_glob(root, f.path());
+				match files.len() {
+					0 => log::warn(format_args!("{:?} doesn't match any files", f.path())),
+					n => log::info(format_args!("expanded {:?} to {n} file(s)", f.path())),
+				}
+				for path in files {
+					let mut f = f.clone();
+					*f.path_mut() = path;
+					insert(
+						direct_url(f.path()),
+						f.into_route(root, mime_types),
+						source.clone(),
+					);
+				}
+			}
+			let mut dir_map: Vec<_> = gr.dirs.drain().collect();
+			dir_map.sort_by(|(a, _), (b, _)| a.cmp(b));
+			for (key, f) in dir_map {
+				let mut prefix = key.strip_prefix('/').unwrap_or(&key).to_string();
+				if prefix.ends_with('/') {
+					prefix.pop();
+				}
+				let route = Route {
+					mime: f.explicit_mime(),
+					pattern: format!("/{prefix}"),
+					..f.into_route(root, mime_types)
+				};
+				if dirs.iter().any(|(p, _)| *p == prefix) {
+					log::warn(format_args!(
+						"ignoring the directory route {key:?} (its prefix {prefix:?} is already used)"
+					));
+					continue;
+				}
+				dirs.push((prefix, route));
+			}
+			// so that the longest matching prefix is found first
+			globs.sort_by_key(|g| std::cmp::Reverse((g.prefix.len(), g.suffix.len())));
+			dirs.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+			Self {
+				exact: get_routes,
+				dirs,
+				globs,
+			}
+		}
+
+		/// All routes of this table
+		fn routes(&self) -> impl Iterator<Item = &Route> {
+			(self.exact.values().flat_map(Route::files))
+				.chain(self.globs.iter().map(|g| &g.route))
+				.chain(self.dirs.iter().map(|(_, r)| r))
+		}
+
+		/// Find the route for `url`, whose files are relative to `root`.
+		///
+		/// Exact routes take priority over directory routes,
+		/// and among the latter, the longest matching prefix wins.
+		fn resolve(
+			&self,
+			root: &Path,
+			mime_types: &MimeTypes,
+			url: &str,
+		) -> Option<Cow<'_, Route>> {
+			let url = normalize_url(url)?;
+			let url = url.as_str();
+			if let Some(route) = self.exact.get(url) {
+				return Some(Cow::Borrowed(route));
+			}
+			if let Some(route) = self
+				.globs
+				.iter()
+				.find_map(|g| g.resolve(root, url, mime_types))
+			{
+				return Some(Cow::Owned(route));
+			}
+			self.dirs.iter().find_map(|(prefix, dir)| {
+				let rest = url.strip_prefix(prefix.as_str())?;
+				let rest = match rest.strip_prefix('/') {
+					Some(rest) => rest,
+					None if prefix.is_empty() => rest,
+					// the directory itself without a trailing slash, which is handled like its subdirectories
+					// (so that relative links in its index file don't break)
+					None if rest.is_empty() => {
+						let path = dir.path.clone();
+						return Some(Cow::Owned(Route {
+							path,
+							..dir.clone()
+						}));
+					}
+					// e.g. `prefix` = "a" and `url` = "ab"
+					None => return None,
+				};
+				dir_file_route(dir, rest, mime_types).map(Cow::Owned)
+			})
+		}
+
+		/// The routes in the format of `Config::dump`, with every line prefixed with `indent`
+		fn dump(&self, indent: &str) -> String {
+			let mut out = String::new();
+			let mut routes: Vec<_> = self.exact.iter().collect();
+			routes.sort_by_key(|(url, _)| *url);
+			out += &format!("{indent}routes:\n");
+			for (url, route) in routes {
+				out += &format!(
+					"{indent}\t{url:?} => {}\n",
+					route.describe(Path::new(""), "no MIME type")
+				);
+			}
+			out += &format!("{indent}wildcard routes:\n");
+			for glob in &self.globs {
+				let url = format!("{}*{}", glob.prefix, glob.suffix);
+				out += &format!(
+					"{indent}\t{url:?} => {}\n",
+					glob.route.describe(Path::new(""), "inferred per file")
+				);
+			}
+			out += &format!("{indent}directory routes:\n");
+			for (prefix, route) in &self.dirs {
+				out += &format!(
+					"{indent}\t{prefix:?} => {}\n",
+					route.describe(Path::new(""), "inferred per file")
+				);
+			}
+			out
+		}
+
+		/// Log all routes, with their files relative to `root` and `context` after their URLs
+		fn log(&self, root: &Path, context: &str) {
+			let mut routes: Vec<_> = self.exact.iter().collect();
+			routes.sort_by_key(|(url, _)| *url);
+			for (url, route) in routes {
+				let description = route.describe(root, "no MIME type");
+				log::requested(format_args!("route {url:?}{context} => {description}"));
+			}
+			for glob in &self.globs {
+				let url = format!("{}*{}", glob.prefix, glob.suffix);
+				let description = glob.route.describe(root, "inferred per file");
+				log::requested(format_args!(
+					"wildcard route {url:?}{context} => {description}"
+				));
+			}
+			for (prefix, route) in &self.dirs {
+				let description = route.describe(root, "inferred per file");
+				log::requested(format_args!(
+					"directory route {prefix:?}{context} => {description}"
+				));
+			}
+		}
+	}
+
+	/// A `vhost` section
+	#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+	pub struct VirtualHostConfig {
+		/// The hosts that this section applies to, `*.` matches any subdomain
+		pub hosts: Vec<String>,
+		#[serde(rename = "404")]
+		pub not_found: Option<Utf8PathBuf>,
+		pub get_routes: Option<GetRoutes>,
+	}
+
+	/// A processed `vhost` section
+	#[derive(Debug, Clone, Eq, PartialEq)]
+	pub struct VirtualHost {
+		pub hosts: Vec<String>,
+		pub routes: RouteTable,
+		/// The processed `404` (if given)
+		pub error_pages: HashMap<StatusCode, Route>,
+	}
+
+	#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+	pub struct ConfigContent {
+		/// The address(es) to bind to (missing if it is given as `bind` instead)
+		pub addr: Option<Addrs>,
+		/// The address to bind to as separate host and port (an alternative to `addr`)
+		pub bind: Option<BindConfig>,
+		#[serde(default)]
+		pub failsafe_addrs: Vec<String>,
+		#[serde(rename = "404")]
+		pub not_found: Option<Utf8PathBuf>,
+		#[serde(rename = "500")]
+		pub error_500: Option<Utf8PathBuf>,
+		/// The file that is served (with status 200) for unrouted requests that accept HTML
+		pub fallback: Option<FileObject>,
+		/// The icon that is served for `/favicon.ico` if it has no route
+		pub default_favicon: Option<DefaultFavicon>,
+		/// The files that are served for error responses, by status code
+		#[serde(default)]
+		pub error_pages: HashMap<String, FileObject>,
+		pub get_routes: Option<GetRoutes>,
+		/// Route tables for requests to specific hosts
+		#[serde(default, rename = "vhost")]
+		pub vhosts: Vec<VirtualHostConfig>,
+		/// Whether to compress text-like responses for clients that accept it
+		#[serde(default)]
+		pub compression: bool,
+		/// Whether to serve precompressed variants of route files (e.g. `style.css.br`) if they exist
+		#[serde(default)]
+		pub precompressed: bool,
+		/// Whether route files that only exist gzipped (e.g. `data.json.gz` for `data.json`) are decompressed on the fly
+		#[serde(default)]
+		pub decompress_fallback: bool,
+		/// The encodings used for compression, in order of preference (default: brotli, then gzip)
+		pub compression_algorithms: Option<Vec<Encoding>>,
+		/// The size (in bytes) above which files are streamed instead of read into memory
+		pub stream_threshold: Option<u64>,
+		/// Whether to read all route files into memory at startup
+		#[serde(default)]
+		pub preload: bool,
+		/// The maximum total size (in bytes) of the preloaded files
+		pub preload_max_bytes: Option<u64>,
+		/// Whether to generate a listing for directory routes without an index file
+		#[serde(default)]
+		pub directory_listing: bool,
+		/// How requests that only differ from a route by a trailing slash are handled
+		#[serde(default)]
+		pub trailing_slash: TrailingSlash,
+		/// Whether routes to missing files are an error instead of a warning
+		#[serde(default)]
+		pub fail_on_missing_routes: bool,
+		/// The format of the log output
+		#[serde(default)]
+		pub log_format: LogFormat,
+		/// The format of request logs in the text format (a template or a preset)
+		pub log_format_template: Option<String>,
+		/// Which messages are logged
+		#[serde(default)]
+		pub log_level: LogLevel,
+		/// Whether requests with a 404 response are logged (default `true`)
+		pub log_404: Option<bool>,
+		/// If set, requests with a 404 response are only counted and the count is logged every this many seconds
+		pub log_404_summary_secs: Option<NonZeroU64>,
+		/// The file that logs are written to instead of stderr (and stdout)
+		pub log_file: Option<Utf8PathBuf>,
+		/// The file that request logs are written to instead of stdout
+		pub access_log_file: Option<Utf8PathBuf>,
+		/// Headers that are added to every response (unless it already sets them)
+		#[serde(default)]
+		pub default_headers: HashMap<String, String>,
+		/// The values that `{{var}}` placeholders are replaced with in routes with `template`
+		#[serde(default)]
+		pub template_vars: HashMap<String, String>,
+		/// The `Server` header of every response
+		pub server_header: Option<ServerHeader>,
+		/// Whether responses with a `Content-Type` carry `X-Content-Type-Options: nosniff` (default `true`)
+		pub nosniff: Option<bool>,
+		/// The file that the bound addresses are written to (e.g. to find out the port when binding port 0)
+		pub bound_addr_file: Option<Utf8PathBuf>,
+		/// Whether to listen on all of `addr` and `failsafe_addrs` instead of only the first that works
+		#[serde(default)]
+		pub bind_all: bool,
+		/// Options for the TCP sockets
+		#[serde(default)]
+		pub socket: SocketConfig,
+		/// Options for HTTP connections
+		#[serde(default)]
+		pub http: HttpConfig,
+		/// If present, the server uses HTTPS
+		pub tls: Option<TlsConfig>,
+		/// Whether HTTP/2 is accepted (default `true`)
+		pub http2: Option<bool>,
+		/// The time (in seconds) after which handling a request is aborted
+		pub request_timeout_secs: Option<u64>,
+		/// The maximum `Content-Length` of requests
+		pub max_request_bytes: Option<u64>,
+		/// The number of requests after which the server shuts down
+		pub max_requests: Option<NonZeroU64>,
+		/// The credentials for routes that require authentication
+		pub auth: Option<AuthConfig>,
+		/// If present, cross-origin requests are allowed
+		pub cors: Option<CorsConfig>,
+		/// If present, the number of requests per client is limited
+		pub rate_limit: Option<RateLimitConfig>,
+		/// Whether the client address and scheme are taken from the `X-Forwarded-For` and `X-Forwarded-Proto` headers
+		#[serde(default)]
+		pub trust_proxy: bool,
+		/// The number of trusted proxies that append to `X-Forwarded-For` (if unset, the leftmost address is used)
+		pub trusted_proxy_hops: Option<NonZeroUsize>,
+		/// The hosts that requests are accepted for (all if empty), `*.` matches any subdomain
+		#[serde(default)]
+		pub allowed_hosts: Vec<String>,
+		/// The URL that always answers with 200 (for health checks)
+		pub health_path: Option<String>,
+		/// The URL that answers with the metrics of the server
+		pub metrics_path: Option<String>,
+		/// The URL that answers with a JSON list of the routes
+		pub routes_path: Option<String>,
+		/// The URL that streams server-sent events for changed files (with `--watch` or `--dev`)
+		pub events_path: Option<String>,
+		/// URLs that redirect elsewhere instead of serving a file
+		#[serde(default)]
+		pub redirects: HashMap<String, RedirectConfig>,
+		/// Rules that route requests as other URLs (the first matching one applies)
+		#[serde(default, rename = "rewrite")]
+		pub rewrites: Vec<RewriteConfig>,
+		/// Additional MIME types by file extension, overriding the built-in ones
+		#[serde(default)]
+		pub mime_types: HashMap<String, String>,
+		/// The MIME type of files whose type can't be inferred from their extension
+		pub default_mime: Option<String>,
+	}
+
+	#[derive(Debug, Clone, Eq, PartialEq)]
+	pub struct Config {
+		/// The arguments the config was loaded with, used for reloading.
+		pub args: crate::cli::Args,
+		/// Whether the config was loaded from the file `args.config` (and is reloaded when it changes),
+		/// as opposed to being built with a [`ConfigBuilder`]
+		pub from_file: bool,
+		/// The path the config file is in, used for logging.
+		pub file_dir: PathBuf,
+		pub content: ConfigContent,
+		/// The processed `get_routes`
+		pub routes: RouteTable,
+		/// The processed `vhost` sections
+		pub vhosts: Vec<VirtualHost>,
+		/// The processed `error_pages` (including `not_found`)
+		pub error_pages: HashMap<StatusCode, Route>,
+		/// The processed `fallback` route
+		pub fallback: Option<Route>,
+		/// The processed `default_favicon`
+		pub favicon: Option<Favicon>,
+		/// The processed `log_format_template`
+		pub log_template: Option<log::Template>,
+		/// The processed `default_headers`
+		pub default_headers: HeaderMap,
+		/// The value of the `Server` header (if `server_header` sets one)
+		pub server_header_value: Option<HeaderValue>,
+		/// The `WWW-Authenticate` header value for responses to unauthorized requests
+		pub auth_challenge: Option<HeaderValue>,
+		/// The processed `cors` section
+		pub cors: Option<CorsPolicy>,
+		/// The processed `redirects` (without leading slashes)
+		pub redirects: HashMap<String, Redirect>,
+		/// The processed `rewrite` sections
+		pub rewrites: Vec<Rewrite>,
+		/// The processed `mime_types` and `default_mime`
+		pub mime_types: MimeTypes,
+		/// The processed `addr` (or `bind`) and `failsafe_addrs`.
+		///
+		/// All groups are listened on, each at the first of its addresses that works (or at all of them with `bind_all`).
+		pub addrs: Vec<Vec<String>>,
+	}
+
+	impl Deref for Config {
+		type Target = ConfigContent;
+
+		fn deref(&self) -> &Self::Target {
+			&self.content
+		}
+	}
+
+	impl DerefMut for Config {
+		fn deref_mut(&mut self) -> &mut Self::Target {
+			&mut self.content
+		}
+	}
+
+	/// Builds a [`Config`] in code instead of loading it from a config file.
+	///
+	/// Relative paths are relative to the `root` directory (instead of the config file's directory),
+	/// and everything that isn't set has the same default as in a config file.
+	/// Unlike configs loaded from a file, built configs aren't reloaded while the server runs.
+	///
+	/// ```
+	/// use simple_http_server::ConfigBuilder;
+	///
+	/// let config = ConfigBuilder::new("site")
+	///     .addr("127.0.0.1:8080")
+	///     .route("/", "index.html", None)
+	///     .route("/data", "data.bin", Some("application/octet-stream"))
+	///     .not_found("404.html")
+	///     .configure(|c| c.compression = true)
+	///     .build()
+	///     .unwrap();
+	/// assert!(config.resolve_route(None, "/").is_some());
+	/// assert!(config.resolve_route(None, "/missing").is_none());
+	/// // then run it with `simple_http_server::serve(config).await`
+	/// ```
+	#[derive(Debug, Clone)]
+	pub struct ConfigBuilder {
+		root: PathBuf,
+		content: ConfigContent,
+	}
+
+	impl ConfigBuilder {
+		/// Start building a config whose relative paths are relative to `root`
+		pub fn new(root: impl Into<PathBuf>) -> Self {
+			Self {
+				root: root.into(),
+				content: ConfigContent::default(),
+			}
+		}
+
+		/// Set the address to bind to (like `addr`)
+		pub fn addr(mut self, addr: impl Into<String>) -> Self {
+			self.content.addr = Some(Addrs::One(addr.into()));
+			self
+		}
+
+		/// Add a route from `url` to the file at `path` (like a key of `get_routes`),
+		/// whose MIME type is inferred if `mime` is `None`
+		pub fn route(
+			mut self,
+			url: impl Into<String>,
+			path: impl Into<Utf8PathBuf>,
+			mime: Option<&str>,
+		) -> Self {
+			let path = path.into();
+			let file = match mime {
+				None => FileObject::InferMime(path),
+				Some(mime) => FileObject::Map {
+					r#type: Some(mime.to_string()),
+					path,
+					cache_control: None,
+					headers: HashMap::new(),
+					auth_required: None,
+					download: false,
+					filename: None,
+					template: false,
+				},
+			};
+			let routes = self
+				.content
+				.get_routes
+				.get_or_insert_with(GetRoutes::default);
+			routes.map.insert(url.into(), RouteValue::File(file));
+			self
+		}
+
+		/// Set the file that is served for unrouted requests that accept HTML (like `fallback`)
+		pub fn fallback(mut self, path: impl Into<Utf8PathBuf>) -> Self {
+			self.content.fallback = Some(FileObject::InferMime(path.into()));
+			self
+		}
+
+		/// Set the file that is served with 404 responses (like `404`)
+		pub fn not_found(mut self, path: impl Into<Utf8PathBuf>) -> Self {
+			self.content.not_found = Some(path.into());
+			self
+		}
+
+		/// Change anything else about the config, with the keys of the config file as fields
+		pub fn configure(mut self, f: impl FnOnce(&mut ConfigContent)) -> Self {
+			f(&mut self.content);
+			self
+		}
+
+		/// Process the config like one loaded from a file
+		pub fn build(self) -> Result<Config, ConfigError> {
+			let root = match self.root.is_relative() {
+				true => std::env::current_dir()
+					.map_err(ConfigError::CurrentDir)?
+					.join(&self.root),
+				false => self.root,
+			};
+			let args = crate::cli::Args {
+				config: root.clone(),
+				mode: crate::cli::Mode::Serve,
+				port: None,
+				log_level: None,
+				list_routes: false,
+				watch: false,
+				dev: false,
+			};
+			Config::process(args, self.content, root, false)
+		}
+	}
+
+	/// Why a config couldn't be loaded
+	#[derive(Debug)]
+	pub enum ConfigError {
+		/// The config file couldn't be read
+		FileOpen(std::io::Error),
+		/// The config file isn't valid TOML or doesn't match the expected structure
+		Parse(toml::de::Error),
+		/// The config file path has no parent directory
+		NoParentDir,
+		/// The current directory (that a relative config file path is relative to) couldn't be determined
+		CurrentDir(std::io::Error),
+		/// The `include` key of `file` isn't a list of paths
+		MalformedInclude {
+			file: PathBuf,
+			error: toml::de::Error,
+		},
+		/// An included file couldn't be read
+		IncludeOpen {
+			file: PathBuf,
+			error: std::io::Error,
+		},
+		/// An included file isn't valid TOML
+		IncludeParse {
+			file: PathBuf,
+			error: toml::de::Error,
+		},
+		/// A file (transitively) includes itself
+		IncludeCycle(PathBuf),
+		/// A string contains a `${` without a closing `}`
+		UnterminatedEnvVar(String),
+		/// The environment variable `name` in the string `value` couldn't be expanded
+		EnvVar {
+			name: String,
+			value: String,
+			error: std::env::VarError,
+		},
+		/// Neither `addr` nor `bind` is given
+		MissingAddr,
+		/// Both `addr` and `bind` are given
+		AddrAndBind,
+		/// The port of a unix socket address can't be overridden
+		UnixPortOverride,
+		/// The port of a list of addresses can't be overridden
+		ListPortOverride,
+		/// The address has no port that could be overridden
+		NoPort(String),
+		/// `failsafe_addrs` is given with a list of addresses
+		ListWithFailsafe,
+		/// A `vhost` has no `hosts`
+		VhostWithoutHosts,
+		/// The value of the key `key` is invalid
+		InvalidValue { key: String, value: String },
+		/// The target of the redirect for `url` isn't a valid header value
+		InvalidRedirect { url: String, to: String },
+		/// The `to` of a `rewrite` uses a capture that `from` doesn't have
+		InvalidRewrite { from: String, to: String },
+		/// Some routes require authentication, but there is no `auth` section
+		MissingAuth,
+		/// The credentials for the `auth.mode` are missing
+		MissingCredentials(AuthMode),
+		/// Routes point to missing files with `fail_on_missing_routes` (with the number of such routes)
+		MissingRouteFiles(usize),
+		/// The `log_format_template` is invalid
+		LogFormatTemplate(String),
+	}
+
+	impl Display for ConfigError {
+		fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+			match self {
+				Self::FileOpen(e) => write!(f, "failed to open file ({e})"),
+				Self::Parse(e) => write!(f, "malformed config file ({e})"),
+				Self::NoParentDir => write!(f, "config file path has no parent directory"),
+				Self::CurrentDir(e) => write!(f, "failed to get current dir ({e})"),
+				Self::MalformedInclude { file, error } => {
+					write!(f, "malformed `include` in {file:?} ({error})")
+				}
+				Self::IncludeOpen { file, error } => {
+					write!(f, "failed to open included file {file:?} ({error})")
+				}
+				Self::IncludeParse { file, error } => {
+					write!(f, "malformed included file {file:?} ({error})")
+				}
+				Self::IncludeCycle(file) => write!(f, "{file:?} includes itself"),
+				Self::UnterminatedEnvVar(value) => write!(f, "unterminated `${{` in {value:?}"),
+				Self::EnvVar { name, value, error } => {
+					write!(f, "failed to expand `${{{name}}}` in {value:?} ({error})")
+				}
+				Self::MissingAddr => write!(f, "missing `addr` (or `bind`)"),
+				Self::AddrAndBind => write!(f, "only one of `addr` and `bind` can be given"),
+				Self::UnixPortOverride => {
+					write!(f, "can't override the port of a unix socket address")
+				}
+				Self::ListPortOverride => {
+					write!(f, "can't override the port of a list of addresses")
+				}
+				Self::NoPort(addr) => write!(f, "`addr` {addr:?} has no port to override"),
+				Self::ListWithFailsafe => write!(
+					f,
+					"`failsafe_addrs` can't be used with a list of addresses (use a list within `addr` instead)"
+				),
+				Self::VhostWithoutHosts => {
+					write!(f, "every `vhost` needs at least one host in `hosts`")
+				}
+				Self::InvalidValue { key, value } => write!(f, "invalid `{key}` value {value:?}"),
+				Self::InvalidRedirect { url, to } => {
+					write!(f, "invalid redirect target {to:?} for {url:?}")
+				}
+				Self::InvalidRewrite { from, to } => {
+					write!(
+						f,
+						"the rewrite from {from:?} to {to:?} uses an unknown capture"
+					)
+				}
+				Self::MissingAuth => write!(
+					f,
+					"some routes require authentication, but there is no `auth` section"
+				),
+				Self::MissingCredentials(AuthMode::Basic) => {
+					write!(f, "`auth.mode` is \"basic\", but `auth.users` is empty")
+				}
+				Self::MissingCredentials(AuthMode::Bearer) => {
+					write!(f, "`auth.mode` is \"bearer\", but `auth.token` is missing")
+				}
+				Self::MissingRouteFiles(n) => write!(f, "{n} route(s) point to missing files"),
+				Self::LogFormatTemplate(e) => write!(f, "{e}"),
+			}
+		}
+	}
+
+	impl std::error::Error for ConfigError {
+		fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+			match self {
+				Self::FileOpen(e) | Self::CurrentDir(e) | Self::IncludeOpen { error: e, .. } => {
+					Some(e)
+				}
+				Self::Parse(e)
+				| Self::MalformedInclude { error: e, .. }
+				| Self::IncludeParse { error: e, .. } => Some(e),
+				Self::EnvVar { error, .. } => Some(error),
+				_ => None,
+			}
+		}
+	}
+
+	impl Config {
+		fn get_root(config_path: &std::path::Path) -> Result<PathBuf, ConfigError> {
+			let mut root = config_path
+				.parent()
+				.ok_or(ConfigError::NoParentDir)?
+				.to_path_buf();
+
+			if root.is_relative() {
+				root = std::env::current_dir()
+					.map_err(ConfigError::CurrentDir)?
+					.join(root);
+			}
+
+			Ok(root)
+		}
+
+		/// Expand the environment variables in `value` (parsed from the file at `path`)
+		/// and merge it over the files it includes.
+		///
+		/// `parents` are the files that (transitively) include `path`, for detecting cycles.
+		fn resolve_includes(
+			path: &Path,
+			mut value: toml::Value,
+			parents: &mut Vec<PathBuf>,
+		) -> Result<toml::Value, ConfigError> {
+			expand_env_vars(&mut value)?;
+			let Some(includes) = value.as_table_mut().and_then(|t| t.remove("include")) else {
+				return Ok(value);
+			};
+			let includes: Vec<Utf8PathBuf> =
+				includes
+					.try_into()
+					.map_err(|error| ConfigError::MalformedInclude {
+						file: path.to_path_buf(),
+						error,
+					})?;
+
+			parents.push(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+			let dir = path.parent().unwrap_or(Path::new(""));
+			let mut merged = toml::Value::Table(toml::Table::new());
+			for include in includes {
+				let include = dir.join(include.as_std_path());
+				let canonical = include.canonicalize().unwrap_or_else(|_| include.clone());
+				if parents.contains(&canonical) {
+					return Err(ConfigError::IncludeCycle(include));
+				}
+				let s = std::fs::read_to_string(&include).map_err(|error| {
+					ConfigError::IncludeOpen {
+						file: include.clone(),
+						error,
+					}
+				})?;
+				let value = toml::from_str(&s).map_err(|error| ConfigError::IncludeParse {
+					file: include.clone(),
+					error,
+				})?;
+				merge_toml(
+					&mut merged,
+					Self::resolve_includes(&include, value, parents)?,
+				);
+			}
+			parents.pop();
+			merge_toml(&mut merged, value);
+			Ok(merged)
+		}
+
+		pub fn new(args: crate::cli::Args) -> Result<Self, ConfigError> {
+			let s = std::fs::read_to_string(&args.config).map_err(ConfigError::FileOpen)?;
+			let value: toml::Value = toml::from_str(&s).map_err(ConfigError::Parse)?;
+			if value.get("include").is_none() {
+				// parsing into the typed struct first gives error messages that point into the file
+				// (with includes, the file alone may be incomplete)
+				let _: ConfigContent = toml::from_str(&s).map_err(ConfigError::Parse)?;
+			}
+			let value = Self::resolve_includes(&args.config, value, &mut vec![])?;
+			let content = ConfigContent::deserialize(value).map_err(ConfigError::Parse)?;
+
+			let root = Self::get_root(&args.config)?;
+			Self::process(args, content, root, true)
+		}
+
+		/// Process `content`, whose relative paths are relative to `root`
+		fn process(
+			args: crate::cli::Args,
+			mut content: ConfigContent,
+			root: PathBuf,
+			from_file: bool,
+		) -> Result<Self, ConfigError> {
+			if args.port.is_some() {
+				// the overridden port only applies to the main address
+				content.failsafe_addrs.clear();
+			}
+			let failsafe_addrs = &content.failsafe_addrs;
+			let addrs = match (&content.addr, &content.bind) {
+				(None, None) => return Err(ConfigError::MissingAddr),
+				(Some(_), Some(_)) => return Err(ConfigError::AddrAndBind),
+				(None, Some(bind)) => {
+					let mut bind = bind.clone();
+					bind.port = args.port.unwrap_or(bind.port);
+					vec![std::iter::once(bind.addr())
+						.chain(failsafe_addrs.clone())
+						.collect()]
+				}
+				(Some(Addrs::One(addr)), None) => {
+					let addr = match args.port {
+						Some(port) => override_port(addr, port)?,
+						None => addr.clone(),
+					};
+					vec![std::iter::once(addr)
+						.chain(failsafe_addrs.clone())
+						.collect()]
+				}
+				(Some(Addrs::Many(_)), None) if args.port.is_some() => {
+					return Err(ConfigError::ListPortOverride);
+				}
+				(Some(Addrs::Many(_)), None) if !failsafe_addrs.is_empty() => {
+					return Err(ConfigError::ListWithFailsafe);
+				}
+				(Some(Addrs::Many(groups)), None) => groups.iter().map(AddrGroup::addrs).collect(),
+			};
+
+			let extensions = std::mem::take(&mut content.mime_types)
+				.into_iter()
+				.filter_map(|(extension, mime)| {
+					let extension = extension.strip_prefix('.').unwrap_or(&extension);
+					match Mime::from_str(&mime) {
+						Ok(mime) => Some((extension.to_ascii_lowercase(), mime)),
+						Err(e) => {
+							log::warn(format_args!(
+								"ignoring invalid MIME type {mime:?} for {extension:?} ({e})"
+							));
+							None
+						}
+					}
+				})
+				.collect();
+			let default = (content.default_mime.as_ref())
+				.map(|mime| {
+					Mime::from_str(mime).map_err(|_| ConfigError::InvalidValue {
+						key: "default_mime".to_string(),
+						value: mime.clone(),
+					})
+				})
+				.transpose()?;
+			let mime_types = MimeTypes {
+				extensions,
+				default,
+			};
+
+			let routes = match &mut content.get_routes {
+				Some(gr) => RouteTable::new(gr, &root, &mime_types),
+				None => RouteTable::default(),
+			};
+
+			let mut error_pages = HashMap::new();
+			for (status, f) in std::mem::take(&mut content.error_pages) {
+				let code = status
+					.parse()
+					.ok()
+					.and_then(|s| StatusCode::from_u16(s).ok());
+				match code.filter(|c| c.is_client_error() || c.is_server_error()) {
+					Some(code) => {
+						error_pages.insert(code, f.into_route(&root, &mime_types));
+					}
+					None => log::warn(format_args!(
+						"ignoring error page for {status:?} (not an error status code)"
+					)),
+				}
+			}
+			let shorthands = [
+				(StatusCode::NOT_FOUND, content.not_found.take()),
+				(StatusCode::INTERNAL_SERVER_ERROR, content.error_500.take()),
+			];
+			for (status, path) in shorthands {
+				if let Some(path) = path {
+					error_pages.insert(status, error_page_route(path, &root, &mime_types));
+				}
+			}
+
+			let mut vhosts = vec![];
+			for mut vhost in std::mem::take(&mut content.vhosts) {
+				if vhost.hosts.is_empty() {
+					return Err(ConfigError::VhostWithoutHosts);
+				}
+				let routes = match &mut vhost.get_routes {
+					Some(gr) => RouteTable::new(gr, &root, &mime_types),
+					None => RouteTable::default(),
+				};
+				let error_pages = (vhost.not_found)
+					.map(|path| {
+						let route = error_page_route(path, &root, &mime_types);
+						(StatusCode::NOT_FOUND, route)
+					})
+					.into_iter()
+					.collect();
+				vhosts.push(VirtualHost {
+					hosts: vhost.hosts,
+					routes,
+					error_pages,
+				});
+			}
+
+			let default_headers = parse_headers(
+				std::mem::take(&mut content.default_headers),
+				"in `default_headers`",
+			);
+
+			let server_header_value =
+				match &content.server_header {
+					None | Some(ServerHeader::Enabled(false)) => None,
+					Some(ServerHeader::Enabled(true)) => Some(HeaderValue::from_static(concat!(
+						"simple-http-server/",
+						env!("CARGO_PKG_VERSION")
+					))),
+					Some(ServerHeader::Value(v)) => Some(HeaderValue::from_str(v).map_err(
+						|_| ConfigError::InvalidValue {
+							key: "server_header".to_string(),
+							value: v.clone(),
+						},
+					)?),
+				};
+
+			let missing = std::iter::once(&routes)
+				.chain(vhosts.iter().map(|v| &v.routes))
+				.map(|routes| Self::check_route_files(&root, routes, content.decompress_fallback))
+				.sum::<usize>();
+			if content.fail_on_missing_routes && missing > 0 {
+				return Err(ConfigError::MissingRouteFiles(missing));
+			}
+
+			let fallback = content
+				.fallback
+				.take()
+				.map(|f| f.into_route(&root, &mime_types));
+			let log_template = (content.log_format_template.as_deref())
+				.map(log::Template::from_str)
+				.transpose()
+				.map_err(ConfigError::LogFormatTemplate)?;
+			let favicon = match content.default_favicon.take() {
+				None | Some(DefaultFavicon::Builtin(false)) => None,
+				Some(DefaultFavicon::Builtin(true)) => Some(Favicon::Builtin),
+				Some(DefaultFavicon::File(f)) => {
+					Some(Favicon::File(Box::new(f.into_route(&root, &mime_types))))
+				}
+			};
+
+			let all_routes = (routes.routes())
+				.chain(vhosts.iter().flat_map(|v| v.routes.routes()))
+				.chain(&fallback)
+				.chain(match &favicon {
+					Some(Favicon::File(route)) => Some(&**route),
+					_ => None,
+				});
+			let auth_challenge = Self::process_auth(content.auth.as_ref(), all_routes)?;
+			let cors = content.cors.as_ref().map(CorsPolicy::new).transpose()?;
+			let redirects = std::mem::take(&mut content.redirects)
+				.into_iter()
+				.map(|(url, r)| {
+					let location =
+						HeaderValue::from_str(&r.to).map_err(|_| ConfigError::InvalidRedirect {
+							url: url.clone(),
+							to: r.to.clone(),
+						})?;
+					let url = url.strip_prefix('/').map(str::to_string).unwrap_or(url);
+					Ok((
+						url,
+						Redirect {
+							location,
+							permanent: r.permanent,
+						},
+					))
+				})
+				.collect::<Result<_, ConfigError>>()?;
+			let rewrites = (content.rewrites.iter())
+				.map(Rewrite::new)
+				.collect::<Result<_, _>>()?;
+
+			Ok(Self {
+				args,
+				from_file,
+				file_dir: root,
+				content,
+				routes,
+				vhosts,
+				error_pages,
+				fallback,
+				favicon,
+				log_template,
+				default_headers,
+				server_header_value,
+				auth_challenge,
+				cors,
+				redirects,
+				rewrites,
+				mime_types,
+				addrs,
+			})
+		}
+
+		/// Whether requests to `route` have to be authenticated
+		pub fn requires_auth(&self, route: &Route) -> bool {
+			route
+				.auth_required
+				.unwrap_or_else(|| self.auth.as_ref().is_some_and(|a| a.all_routes))
+		}
+
+		/// Validate the `auth` section, returning the `WWW-Authenticate` header value
+		fn process_auth<'a>(
+			auth: Option<&AuthConfig>,
+			mut routes: impl Iterator<Item = &'a Route>,
+		) -> Result<Option<HeaderValue>, ConfigError> {
+			let Some(auth) = auth else {
+				if routes.any(|r| r.auth_required == Some(true)) {
+					return Err(ConfigError::MissingAuth);
+				}
+				return Ok(None);
+			};
+
+			if routes.any(|r| r.auth_required.unwrap_or(auth.all_routes)) {
+				let missing = match auth.mode {
+					AuthMode::Basic => auth.users.is_empty(),
+					AuthMode::Bearer => auth.token.is_none(),
+				};
+				if missing {
+					return Err(ConfigError::MissingCredentials(auth.mode));
+				}
+			}
+
+			let realm = auth.realm.as_deref().unwrap_or("simple-http-server");
+			let challenge = format!("{} realm=\"{realm}\"", auth.mode.scheme());
+			HeaderValue::from_str(&challenge)
+				.ok()
+				.filter(|_| !realm.contains(['"', '\\']))
+				.map(Some)
+				.ok_or_else(|| ConfigError::InvalidValue {
+					key: "auth.realm".to_string(),
+					value: realm.to_string(),
+				})
+		}
+
+		/// Warn about every route whose file (or directory) doesn't exist
+		/// (files that only exist gzipped count as existing with `decompress_fallback`).
+		///
+		/// Returns the number of such routes.
+		fn check_route_files(root: &Path, table: &RouteTable, decompress_fallback: bool) -> usize {
+			let mut routes: Vec<_> = (table.exact.iter())
+				.flat_map(|(url, route)| route.files().map(move |route| (url, route, false)))
+				.collect();
+			routes.sort_by_key(|(url, _, _)| *url);
+			routes.extend(table.dirs.iter().map(|(url, route)| (url, route, true)));
+
+			let mut missing = 0;
+			for (url, route, is_dir) in routes {
+				let path = route.path.strip_prefix(root).unwrap_or(&route.path);
+				if route.source == ContentSource::Embedded {
+					let exists = match is_dir {
+						true => embedded::is_dir(path),
+						false => embedded::get(path).is_some(),
+					};
+					if !exists {
+						log::warn(format_args!(
+							"the embedded file {path:?} of route {url:?} doesn't exist"
+						));
+						missing += 1;
+					}
+					continue;
+				}
+				let gzipped = || {
+					let mut gz = route.path.clone().into_os_string();
+					gz.push(".gz");
+					Path::new(&gz).is_file()
+				};
+				let problem = match std::fs::metadata(&route.path) {
+					Ok(m) if m.is_dir() == is_dir && (is_dir || m.is_file()) => continue,
+					Err(e)
+						if e.kind() == std::io::ErrorKind::NotFound
+							&& decompress_fallback
+							&& !is_dir && gzipped() =>
+					{
+						continue
+					}
+					Ok(_) if is_dir => "is not a directory".to_string(),
+					Ok(_) => "is not a regular file".to_string(),
+					Err(e) => format!("can't be accessed ({e})"),
+				};
+				log::warn(format_args!("the path {path:?} of route {url:?} {problem}"));
+				missing += 1;
+			}
+			missing
+		}
+
+		/// A human-readable description of how the config was processed
+		pub fn dump(&self) -> String {
+			let mut out = String::from("addresses:\n");
+			for group in &self.addrs {
+				for (i, addr) in group.iter().enumerate() {
+					let failsafe = if i == 0 { "" } else { "\t(failsafe) " };
+					out += &format!("\t{failsafe}{addr}\n");
+				}
+			}
+
+			out += &self.routes.dump("");
+			out += "rewrites:\n";
+			for rewrite in &self.rewrites {
+				out += &format!("\t{:?} => {:?}\n", rewrite.from, rewrite.to);
+			}
+			let mut error_pages: Vec<_> = self.error_pages.iter().collect();
+			error_pages.sort_by_key(|(status, _)| *status);
+			out += "error pages:\n";
+			for (status, route) in error_pages {
+				out += &format!(
+					"\t{} => {}\n",
+					status.as_u16(),
+					route.describe(Path::new(""), "no MIME type")
+				);
+			}
+			if let Some(fallback) = &self.fallback {
+				out += &format!(
+					"fallback:\n\t{}\n",
+					fallback.describe(Path::new(""), "no MIME type")
+				);
+			}
+			for vhost in &self.vhosts {
+				out += &format!("vhost {}:\n", vhost.hosts.join(", "));
+				out += &vhost.routes.dump("\t");
+				if let Some(route) = vhost.error_pages.get(&StatusCode::NOT_FOUND) {
+					let description = route.describe(Path::new(""), "no MIME type");
+					out += &format!("\t404 page:\n\t\t{description}\n");
+				}
+			}
+			out
+		}
+
+		/// Log all routes, with their files relative to `file_dir`
+		pub fn log_routes(&self) {
+			self.routes.log(&self.file_dir, "");
+			for rewrite in &self.rewrites {
+				log::requested(format_args!(
+					"rewrite {:?} => {:?}",
+					rewrite.from, rewrite.to
+				));
+			}
+			for vhost in &self.vhosts {
+				let context = format!(" (for {})", vhost.hosts.join(", "));
+				vhost.routes.log(&self.file_dir, &context);
+			}
+		}
+
+		/// Apply the logging settings
+		pub fn setup_log(&self) {
+			log::set_format(self.log_format);
+			log::set_template(self.log_template.clone());
+			log::set_level(self.args.log_level.unwrap_or(self.log_level));
+			let path = |p: &Utf8PathBuf| self.file_dir.join(p.as_std_path());
+			log::set_files(
+				self.log_file.as_ref().map(path).as_deref(),
+				self.access_log_file.as_ref().map(path).as_deref(),
+			);
+		}
+
+		/// The encodings used for compression, in order of preference
+		pub fn encodings(&self) -> &[Encoding] {
+			self.compression_algorithms
+				.as_deref()
+				.unwrap_or(&[Encoding::Brotli, Encoding::Gzip])
+		}
+
+		/// Find the redirect for `url`
+		pub fn resolve_redirect(&self, url: &str) -> Option<&Redirect> {
+			self.redirects.get(url.strip_prefix('/').unwrap_or(url))
+		}
+
+		/// Whether requests for `host` are accepted according to `allowed_hosts`
+		pub fn is_allowed_host(&self, host: &str) -> bool {
+			self.allowed_hosts.is_empty()
+				|| (self.allowed_hosts.iter()).any(|allowed| host_matches(allowed, host))
+		}
+
+		/// Whether `url` is the `health_path`
+		pub fn is_health_path(&self, url: &str) -> bool {
+			is_path(self.health_path.as_deref(), url)
+		}
+
+		/// Whether `url` is the `metrics_path`
+		pub fn is_metrics_path(&self, url: &str) -> bool {
+			is_path(self.metrics_path.as_deref(), url)
+		}
+
+		/// Whether `url` is the `routes_path`
+		pub fn is_routes_path(&self, url: &str) -> bool {
+			is_path(self.routes_path.as_deref(), url)
+		}
+
+		/// Whether `url` is the `events_path`
+		pub fn is_events_path(&self, url: &str) -> bool {
+			is_path(self.events_path.as_deref(), url)
+		}
+
+		/// Whether requests for `host` are routed by a `vhost` (and if so, by which)
+		pub fn virtual_host(&self, host: &str) -> Option<usize> {
+			(self.vhosts.iter()).position(|v| v.hosts.iter().any(|h| host_matches(h, host)))
+		}
+
+		/// The routes for requests that are routed by `vhost` (see `virtual_host`)
+		pub fn route_table(&self, vhost: Option<usize>) -> &RouteTable {
+			match vhost {
+				Some(i) => &self.vhosts[i].routes,
+				None => &self.routes,
+			}
+		}
+
+		/// Find the route for `url` in the routes of `vhost`, taking `trailing_slash` into account.
+		pub fn resolve_route(
+			&self,
+			vhost: Option<usize>,
+			url: impl AsRef<str>,
+		) -> Option<Cow<'_, Route>> {
+			let url = url.as_ref();
+			self.resolve_route_strict(vhost, url)
+				.or_else(|| match self.trailing_slash {
+					TrailingSlash::Ignore => {
+						self.resolve_route_strict(vhost, &toggle_trailing_slash(url)?)
+					}
+					_ => None,
+				})
+		}
+
+		/// The URL that a request to `url` should be redirected to according to `trailing_slash`
+		pub fn trailing_slash_redirect(&self, vhost: Option<usize>, url: &str) -> Option<String> {
+			if self.trailing_slash != TrailingSlash::Redirect
+				|| self.resolve_route_strict(vhost, url).is_some()
+			{
+				return None;
+			}
+			let toggled = toggle_trailing_slash(url)?;
+			self.resolve_route_strict(vhost, &toggled)?;
+			Some(toggled)
+		}
+
+		/// The URL that `url` is rewritten to by the first matching `rewrite` (if any)
+		pub fn rewrite(&self, url: &str) -> Option<String> {
+			let url = normalize_url(url)?;
+			self.rewrites.iter().find_map(|r| r.apply(&url))
+		}
+
+		/// Find the route for `url` (after rewriting it) in the routes of `vhost`
+		fn resolve_route_strict(&self, vhost: Option<usize>, url: &str) -> Option<Cow<'_, Route>> {
+			let rewritten = self.rewrite(url);
+			let url = rewritten.as_deref().unwrap_or(url);
+			(self.route_table(vhost)).resolve(&self.file_dir, &self.mime_types, url)
+		}
+	}
+
+	/// Whether `host` matches `pattern`, where `*.` matches any subdomain
+	fn host_matches(pattern: &str, host: &str) -> bool {
+		let host = host.strip_suffix('.').unwrap_or(host);
+		match pattern.strip_prefix("*.") {
+			Some(domain) => host.len().checked_sub(domain.len() + 1).is_some_and(|dot| {
+				host.as_bytes()[dot] == b'.' && host[dot + 1..].eq_ignore_ascii_case(domain)
+			}),
+			None => host.eq_ignore_ascii_case(pattern),
+		}
+	}
+
+	/// The route of an error page given as a path, which is assumed to be HTML if its MIME type is unknown
+	fn error_page_route(path: Utf8PathBuf, root: &Path, mime_types: &MimeTypes) -> Route {
+		let mut route = FileObject::InferMime(path).into_route(root, mime_types);
+		route.mime.get_or_insert(mime::TEXT_HTML_UTF_8);
+		route
+	}
+
+	/// `url` without its trailing slash, or with one if it doesn't have one.
+	///
+	/// Returns `None` for the root URL.
+	fn toggle_trailing_slash(url: &str) -> Option<String> {
+		match url.strip_suffix('/') {
+			Some(stripped) if stripped.trim_matches('/').is_empty() => None,
+			Some(stripped) => Some(stripped.to_string()),
+			None if url.is_empty() => None,
+			None => Some(format!("{url}/")),
+		}
+	}
+
+	/// Percent-decode `url` and normalize it to the form of route keys,
+	/// i.e. without a leading slash, duplicate slashes and `.` segments
+	/// (a trailing slash is kept, since it marks a request for a directory).
+	///
+	/// Returns `None` if it isn't valid UTF-8 afterwards or contains NUL or `..` segments.
+	fn normalize_url(url: &str) -> Option<String> {
+		let url = percent_decode_str(url).decode_utf8().ok()?;
+		if url.contains('\0') {
+			return None;
+		}
+		let mut segments = vec![];
+		let mut trailing_slash = false;
+		for segment in url.split('/') {
+			trailing_slash = matches!(segment, "" | ".");
+			match segment {
+				"" | "." => {}
+				".." => return None,
+				s => segments.push(s),
+			}
+		}
+		let mut normalized = segments.join("/");
+		if trailing_slash && !normalized.is_empty() {
+			normalized.push('/');
+		}
+		Some(normalized)
+	}
+
+	/// A processed route whose URL contains a `*` wildcard
+	#[derive(Debug, Clone, Eq, PartialEq)]
+	pub struct GlobRoute {
+		/// The part of the URL before the `*`
+		pub prefix: String,
+		/// The part of the URL after the `*`
+		pub suffix: String,
+		/// The route (its path and MIME type only apply if `path_parts` is `None`)
+		pub route: Route,
+		/// The parts of the path before and after its `*` (if any),
+		/// which is replaced by the part of the URL that matched the wildcard
+		pub path_parts: Option<(String, String)>,
+	}
+
+	impl GlobRoute {
+		/// Process the route from `url` to `f` if `url` contains a wildcard, otherwise return `f`
+		fn new(
+			url: &str,
+			f: FileObject,
+			root: &Path,
+			mime_types: &MimeTypes,
+		) -> Result<Self, Box<FileObject>> {
+			let Some((prefix, suffix)) = url.split_once('*') else {
+				return Err(Box::new(f));
+			};
+			if suffix.contains('*') {
+				log::warn(format_args!(
+					"treating {url:?} as a normal route (only one `*` is supported)"
+				));
+				return Err(Box::new(f));
+			}
+			let path_parts = f
+				.path()
+				.as_str()
+				.split_once('*')
+				.map(|(a, b)| (a.to_string(), b.to_string()));
+			let route = match path_parts {
+				Some(_) => Route {
+					mime: f.explicit_mime(),
+					..f.into_route(root, mime_types)
+				},
+				None => f.into_route(root, mime_types),
+			};
+			let route = Route {
+				pattern: format!("/{url}"),
+				..route
+			};
+			Ok(Self {
+				prefix: prefix.to_string(),
+				suffix: suffix.to_string(),
+				route,
+				path_parts,
+			})
+		}
+
+		/// The route for `url` if it matches
+		fn resolve(&self, root: &Path, url: &str, mime_types: &MimeTypes) -> Option<Route> {
+			let matched = url
+				.strip_prefix(self.prefix.as_str())?
+				.strip_suffix(self.suffix.as_str())?;
+			let Some((before, after)) = &self.path_parts else {
+				return Some(self.route.clone());
+			};
+			let escapes = matched
+				.split('/')
+				.any(|segment| segment == ".." || segment.contains('\\'));
+			if escapes {
+				return None;
+			}
+			let path = Utf8PathBuf::from(format!("{before}{matched}{after}"));
+			Some(Route {
+				mime: self
+					.route
+					.mime
+					.clone()
+					.or_else(|| infer_mime(&path, mime_types)),
+				path: resolve_file_path(root, &path).1,
+				..self.route.clone()
+			})
+		}
+	}
+
+	/// The route for the file at the relative URL `rest` in the directory of `dir`.
+	///
+	/// Returns `None` if `rest` tries to escape the directory.
+	fn dir_file_route(dir: &Route, rest: &str, mime_types: &MimeTypes) -> Option<Route> {
+		let mut path = dir.path.clone();
+		let mut file_name = None;
+		for segment in rest.split('/') {
+			match segment {
+				"" | "." => {}
+				".." => return None,
+				// this would be a path separator on windows
+				s if s.contains('\\') => return None,
+				s => {
+					path.push(s);
+					file_name = Some(s);
+				}
+			}
+		}
+		let (file_name, dir_index) = match file_name {
+			Some(name) if !rest.ends_with('/') => (name, false),
+			_ => {
+				path.push(INDEX_FILE);
+				(INDEX_FILE, true)
+			}
+		};
+		Some(Route {
+			mime: dir
+				.mime
+				.clone()
+				.or_else(|| infer_mime(Utf8Path::new(file_name), mime_types)),
+			path,
+			dir_index,
+			..dir.clone()
+		})
+	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::*;
+		use crate::testing::{load_config, try_load_config};
+
+		/// The MIME type of the route for `url`
+		fn mime(config: &Config, url: &str) -> Option<String> {
+			let route = config.resolve_route(None, url).unwrap();
+			route.mime.as_ref().map(|m| m.to_string())
+		}
+
+		#[test]
+		fn custom_mime_types() {
+			let config = r#"
+				[mime_types]
+				foo = "application/x-foo"
+				".bar" = "application/x-bar"
+				png = "image/x-custom-png"
+				[get_routes]
+				"a" = "a.foo"
+				"b" = "b.bar"
+				"c" = "c.png"
+				"d" = "d.jpg"
+			"#;
+			let (_dir, config) = load_config(config, &[]);
+			assert_eq!(mime(&config, "a").as_deref(), Some("application/x-foo"));
+			assert_eq!(mime(&config, "b").as_deref(), Some("application/x-bar"));
+			// configured types take priority over the built-in ones
+			assert_eq!(mime(&config, "c").as_deref(), Some("image/x-custom-png"));
+			assert_eq!(mime(&config, "d").as_deref(), Some("image/jpeg"));
+		}
+
+		#[test]
+		fn builtin_mime_types() {
+			let cases = [
+				("json", "application/json"),
+				("xml", "application/xml"),
+				("webp", "image/webp"),
+				("avif", "image/avif"),
+				("gif", "image/gif"),
+				("ico", "image/x-icon"),
+				("woff2", "font/woff2"),
+				("ttf", "font/ttf"),
+				("mp3", "audio/mpeg"),
+				("ogg", "audio/ogg"),
+				("webm", "video/webm"),
+				("csv", "text/csv; charset=utf-8"),
+				("md", "text/markdown; charset=utf-8"),
+			];
+			for (extension, expected) in cases {
+				let mime = builtin_mime(extension).map(|m| m.to_string());
+				assert_eq!(mime.as_deref(), Some(expected), "{extension}");
+			}
+			assert_eq!(builtin_mime("unknown"), None);
+		}
+
+		#[test]
+		fn uppercase_extensions() {
+			let mime_types = MimeTypes {
+				extensions: HashMap::from([("foo".into(), "application/x-foo".parse().unwrap())]),
+				default: None,
+			};
+			let infer =
+				|path: &str| infer_mime(Utf8Path::new(path), &mime_types).map(|m| m.to_string());
+			assert_eq!(infer("PHOTO.JPG").as_deref(), Some("image/jpeg"));
+			assert_eq!(
+				infer("index.HTML").as_deref(),
+				Some("text/html; charset=utf-8")
+			);
+			assert_eq!(infer("a.Foo").as_deref(), Some("application/x-foo"));
+			assert_eq!(infer("README").as_deref(), None);
+		}
+
+		#[test]
+		fn glob_expansion_in_direct() {
+			let config = r#"
+				[get_routes]
+				direct = ["assets/*.css", "other/**/*", { path = "typed/*", type = "text/plain" }, "plain.txt"]
+			"#;
+			let files = [
+				("assets/a.css", ""),
+				("assets/b.css", ""),
+				("assets/c.js", ""),
+				("assets/sub/d.css", ""),
+				("other/e.txt", ""),
+				("other/sub/f.txt", ""),
+				("typed/g", ""),
+				("plain.txt", ""),
+			];
+			let (_dir, config) = load_config(config, &files);
+			let mut urls: Vec<_> = config.routes.exact.keys().map(String::as_str).collect();
+			urls.sort_unstable();
+			let expected = [
+				"assets/a.css",
+				"assets/b.css",
+				"other/e.txt",
+				"other/sub/f.txt",
+				"plain.txt",
+				"typed/g",
+			];
+			assert_eq!(urls, expected);
+			assert_eq!(
+				mime(&config, "assets/a.css").as_deref(),
+				Some("text/css; charset=utf-8")
+			);
+			assert_eq!(mime(&config, "typed/g").as_deref(), Some("text/plain"));
+		}
+
+		#[test]
+		fn keys_with_and_without_leading_slash() {
+			let config = r#"
+				[get_routes]
+				"/a" = "a.txt"
+				"b" = "b.txt"
+				"/" = "index.html"
+			"#;
+			let (_dir, config) = load_config(config, &[]);
+			for url in ["/a", "a", "/b", "b"] {
+				assert!(config.resolve_route(None, url).is_some(), "{url}");
+			}
+			assert_eq!(
+				mime(&config, "/").as_deref(),
+				Some("text/html; charset=utf-8")
+			);
+			assert!(config.resolve_route(None, "//a").is_some());
+		}
+
+		#[test]
+		fn percent_decoding() {
+			assert_eq!(
+				normalize_url("/my%20file.html").as_deref(),
+				Some("my file.html")
+			);
+			assert_eq!(normalize_url("/%C3%A4").as_deref(), Some("ä"));
+			// a NUL character, invalid UTF-8 and an encoded `..`
+			for url in ["/a%00b", "/%FF", "/a/%2E%2E/b", "/a/..%2Fb"] {
+				assert_eq!(normalize_url(url), None, "{url}");
+			}
+		}
+
+		#[test]
+		fn url_normalization() {
+			let cases = [
+				("/a/b", "a/b"),
+				("a/b", "a/b"),
+				("//a///b", "a/b"),
+				("/a/./b/.", "a/b/"),
+				("/./a/b/", "a/b/"),
+				("/", ""),
+				("", ""),
+				("/./", ""),
+			];
+			for (url, expected) in cases {
+				assert_eq!(normalize_url(url).as_deref(), Some(expected), "{url}");
+			}
+			for url in [
+				"/..",
+				"/a/../b",
+				"/a/b/..",
+				"/../etc/passwd",
+				"/a/%2e%2e/%2e%2e/etc",
+			] {
+				assert_eq!(normalize_url(url), None, "{url}");
+			}
+		}
+
+		#[test]
+		fn bind_and_addr() {
+			let (_dir, addr) = try_load_config("addr = \"127.0.0.1:8080\"\n", &[]);
+			let bind = "[bind]\nhost = \"127.0.0.1\"\nport = 8080\n";
+			let (_dir, bind) = try_load_config(bind, &[]);
+			assert_eq!(addr.unwrap().addrs, [["127.0.0.1:8080"]]);
+			assert_eq!(bind.unwrap().addrs, [["127.0.0.1:8080"]]);
+			let ipv6 = "[bind]\nhost = \"::1\"\nport = 8080\n";
+			assert_eq!(
+				try_load_config(ipv6, &[]).1.unwrap().addrs,
+				[["[::1]:8080"]]
+			);
+
+			let both = "addr = \"127.0.0.1:8080\"\n[bind]\nhost = \"127.0.0.1\"\nport = 8080\n";
+			assert!(try_load_config(both, &[]).1.is_err());
+			assert!(try_load_config("", &[]).1.is_err());
+		}
+
+		#[test]
+		fn host_patterns() {
+			assert!(host_matches("example.com", "example.com"));
+			assert!(host_matches("example.com", "EXAMPLE.com"));
+			assert!(!host_matches("example.com", "www.example.com"));
+			assert!(host_matches("*.example.com", "www.example.com"));
+			assert!(host_matches("*.example.com", "a.b.example.com"));
+			assert!(!host_matches("*.example.com", "example.com"));
+			assert!(!host_matches("*.example.com", "badexample.com"));
+		}
+
+		#[test]
+		fn rewrites() {
+			let config = r#"
+				[[rewrite]]
+				from = "/blog/:slug"
+				to = "/posts/:slug.html"
+				[[rewrite]]
+				from = "users/:user/files/:file"
+				to = "/files/:user-:file"
+			"#;
+			let (_dir, config) = load_config(config, &[]);
+			assert_eq!(
+				config.rewrite("blog/hello"),
+				Some("/posts/hello.html".into())
+			);
+			assert_eq!(config.rewrite("users/a/files/b"), Some("/files/a-b".into()));
+			for url in ["blog", "blog/", "blog/a/b", "other/hello", "users/a/files"] {
+				assert_eq!(config.rewrite(url), None, "{url}");
+			}
+
+			let config = "[[rewrite]]\nfrom = \"/blog/:slug\"\nto = \"/posts/:other\"\n";
+			let (_dir, result) = try_load_config(&format!("addr = \"127.0.0.1:0\"\n{config}"), &[]);
+			assert!(matches!(result, Err(ConfigError::InvalidRewrite { .. })));
+		}
+	}
+}
+
+pub mod http {
+	use std::borrow::Cow;
+	use std::collections::hash_map::RandomState;
+	use std::collections::{BTreeMap, HashMap};
+	use std::convert::Infallible;
+	use std::fmt::Write;
+	use std::future::Future;
+	use std::hash::{BuildHasher, Hasher};
+	use std::io::SeekFrom;
+	use std::marker::PhantomData;
+	use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+	use std::num::NonZeroUsize;
+	use std::path::{Path, PathBuf};
+	use std::pin::Pin;
+	use std::str::FromStr;
+	use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+	use std::sync::{Arc, Mutex, RwLock};
+	use std::task::{Context, Poll};
+	use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+	use arc_swap::ArcSwap;
+	use base64::prelude::{Engine, BASE64_STANDARD};
+
+	use axum::body::{Body, Bytes};
+	use axum::extract::ConnectInfo;
+	use axum::handler::Handler;
+	use axum::http::header::{
+		HeaderName, ACCEPT, ACCEPT_ENCODING, ACCEPT_RANGES, ACCESS_CONTROL_ALLOW_HEADERS,
+		ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE,
+		ACCESS_CONTROL_REQUEST_METHOD, ALLOW, AUTHORIZATION, CACHE_CONTROL, CONNECTION,
+		CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+		HOST, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, LOCATION, ORIGIN, RANGE,
+		RETRY_AFTER, SERVER, VARY, WWW_AUTHENTICATE, X_CONTENT_TYPE_OPTIONS,
+	};
+	use axum::http::request::Parts;
+	use axum::http::uri::Authority;
+	use axum::http::{HeaderMap, HeaderValue, Method, Request, StatusCode, Uri, Version};
+	use axum::response::{IntoResponse, IntoResponseParts};
+	use axum::serve::ListenerExt;
+	use flate2::read::GzDecoder;
+	use flate2::write::GzEncoder;
+	use flate2::Compression;
+	use hyper::body::Incoming;
+	use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+	use hyper_util::server::conn::auto;
+	use hyper_util::server::graceful::GracefulShutdown;
+	use hyper_util::service::TowerToHyperService;
+	use mime::Mime;
+	use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+	use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+	use tokio::fs::File;
+	use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, DuplexStream, Take};
+	use tokio::net::TcpListener;
+	#[cfg(unix)]
+	use tokio::net::UnixListener;
+	use tokio::sync::{broadcast, OwnedSemaphorePermit, Semaphore};
+	use tokio_util::io::ReaderStream;
+	use tokio_util::sync::CancellationToken;
+
+	use super::cli::Args;
+	use super::config::{
+		AuthMode, Config, ContentSource, CorsPolicy, Encoding, Favicon, HttpConfig,
+		RateLimitConfig, Route, RouteTable, ServerHeader, SocketConfig, TrailingSlash, UNIX_PREFIX,
+	};
+	use super::embedded;
+	use super::log::{self, LogLevel};
+
+	#[derive(Debug, Clone)]
+	struct SetMime(Mime);
+
+	impl IntoResponseParts for SetMime {
+		type Error = (StatusCode, HeaderMap, String);
+
+		fn into_response_parts(
+			self,
+			mut res: axum::response::ResponseParts,
+		) -> Result<axum::response::ResponseParts, Self::Error> {
+			let value = HeaderValue::from_str(self.0.as_ref()).map_err(|e| {
+				(
+					StatusCode::INTERNAL_SERVER_ERROR,
+					HeaderMap::from_iter([(
+						CONTENT_TYPE,
+						HeaderValue::from_static(mime::TEXT_PLAIN_UTF_8.as_ref()),
+					)]),
+					format!("invalid MIME type for header: {e}"),
+				)
+			})?;
+			res.headers_mut().insert(CONTENT_TYPE, value);
+			Ok(res)
+		}
+	}
+
+	#[derive(Debug)]
+	enum Response {
+		PureCode(StatusCode),
+		MimeBody(StatusCode, Option<SetMime>, Bytes),
+		/// Like `MimeBody`, but only with the length of the body (used for HEAD requests)
+		MimeHead(StatusCode, Option<SetMime>, u64),
+		/// Like `MimeBody`, but the body (of the given length) is streamed from the file
+		MimeStream(StatusCode, Option<SetMime>, u64, Take<File>),
+		/// A response without body that lists the supported methods in the `Allow` header
+		Allow(StatusCode),
+		/// A stream of server-sent events (written to the other end of the duplex stream)
+		EventStream(DuplexStream),
+		/// Another response with additional headers
+		WithHeaders(HeaderMap, Box<Response>),
+		/// A response of the `FallbackHandler`
+		Custom(axum::response::Response),
+	}
+
+	/// The methods the server supports, as listed in the `Allow` header
+	const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+
+	impl Response {
+		fn status(&self) -> StatusCode {
+			match self {
+				Self::PureCode(c)
+				| Self::MimeBody(c, ..)
+				| Self::MimeHead(c, ..)
+				| Self::MimeStream(c, ..)
+				| Self::Allow(c) => *c,
+				Self::EventStream(_) => StatusCode::OK,
+				Self::WithHeaders(_, r) => r.status(),
+				Self::Custom(r) => r.status(),
+			}
+		}
+
+		fn into_head(self) -> Self {
+			match self {
+				Self::MimeBody(c, m, b) => Self::MimeHead(c, m, b.len() as u64),
+				Self::MimeStream(c, m, len, _) => Self::MimeHead(c, m, len),
+				Self::WithHeaders(h, r) => Self::WithHeaders(h, Box::new(r.into_head())),
+				r => r,
+			}
+		}
+
+		/// Replace the body of this (error) response with the page for its status (if any)
+		fn with_error_page(self, pages: &HashMap<StatusCode, ErrorPage>) -> Self {
+			let Some(page) = pages.get(&self.status()) else {
+				return self;
+			};
+			match self {
+				Self::WithHeaders(h, r) => Self::WithHeaders(h, Box::new(r.with_error_page(pages))),
+				Self::PureCode(c) | Self::MimeBody(c, ..) => {
+					Self::MimeBody(c, page.mime.clone(), page.data.clone())
+				}
+				Self::Allow(c) => Self::MimeBody(c, page.mime.clone(), page.data.clone())
+					.with_header(ALLOW, HeaderValue::from_static(ALLOWED_METHODS)),
+				r => r,
+			}
+		}
+
+		/// Inject the live reload script into this response if it is a (complete and uncompressed) HTML page
+		fn with_live_reload(self) -> Self {
+			match self {
+				Self::WithHeaders(h, r) if !h.contains_key(CONTENT_ENCODING) => {
+					Self::WithHeaders(h, Box::new(r.with_live_reload()))
+				}
+				Self::MimeBody(c, Some(m), b) if c == StatusCode::OK && is_html(&m.0) => {
+					Self::MimeBody(c, Some(m), inject_live_reload(&b))
+				}
+				r => r,
+			}
+		}
+
+		fn with_headers(self, headers: &HeaderMap) -> Self {
+			headers.iter().fold(self, |r, (name, value)| {
+				r.with_header(name.clone(), value.clone())
+			})
+		}
+
+		fn with_header(self, name: HeaderName, value: HeaderValue) -> Self {
+			match self {
+				Self::WithHeaders(mut h, r) => {
+					h.insert(name, value);
+					Self::WithHeaders(h, r)
+				}
+				r => Self::WithHeaders(HeaderMap::from_iter([(name, value)]), Box::new(r)),
+			}
+		}
+
+		/// Add `name` to the `Vary` header
+		fn with_vary(self, name: &'static str) -> Self {
+			match self {
+				Self::WithHeaders(mut h, r) => {
+					let value = match h.get(VARY).and_then(|v| v.to_str().ok()) {
+						Some(vary) => header_value(format!("{vary}, {name}")),
+						None => HeaderValue::from_static(name),
+					};
+					h.insert(VARY, value);
+					Self::WithHeaders(h, r)
+				}
+				r => r.with_header(VARY, HeaderValue::from_static(name)),
+			}
+		}
+	}
+
+	// No `Date` header is set here: hyper adds one to every response (HTTP/1 and HTTP/2) that doesn't have one yet.
+	impl IntoResponse for Response {
+		fn into_response(self) -> axum::response::Response {
+			match self {
+				Self::PureCode(c) => c.into_response(),
+				Self::Allow(c) => {
+					(c, [(ALLOW, HeaderValue::from_static(ALLOWED_METHODS))]).into_response()
+				}
+				Self::MimeBody(c, m, b) => {
+					// this is set explicitly (instead of leaving it to the body framing)
+					// so proxies and HTTP/1.0 clients know the length up front
+					let len = [(CONTENT_LENGTH, HeaderValue::from(b.len()))];
+					match m {
+						None => {
+							let mut response = (c, len, b).into_response();
+							// `Bytes` are sent as `application/octet-stream` by default,
+							// but without a known type, browsers should be able to guess it
+							response.headers_mut().remove(CONTENT_TYPE);
+							response
+						}
+						Some(m) => (c, m, len, b).into_response(),
+					}
+				}
+				Self::MimeStream(c, m, len, file) => {
+					let len = [(CONTENT_LENGTH, HeaderValue::from(len))];
+					let body = Body::from_stream(ReaderStream::new(file));
+					match m {
+						None => (c, len, body).into_response(),
+						Some(m) => (c, m, len, body).into_response(),
+					}
+				}
+				Self::WithHeaders(h, r) => (h, *r).into_response(),
+				Self::EventStream(events) => {
+					let headers = [
+						(CONTENT_TYPE, HeaderValue::from_static("text/event-stream")),
+						(CACHE_CONTROL, HeaderValue::from_static("no-cache")),
+					];
+					let body = Body::from_stream(ReaderStream::new(events));
+					(headers, body).into_response()
+				}
+				Self::MimeHead(c, m, len) => {
+					let len = [(CONTENT_LENGTH, HeaderValue::from(len))];
+					match m {
+						None => (c, len).into_response(),
+						Some(m) => (c, m, len).into_response(),
+					}
+				}
+				// hyper doesn't send the body in responses to HEAD requests
+				Self::Custom(r) => r,
+			}
+		}
+	}
+
+	/// A page that is loaded at startup and served for every error of its status
+	#[derive(Debug, Clone)]
+	struct ErrorPage {
+		mime: Option<SetMime>,
+		data: Bytes,
+	}
+
+	/// Everything the request handler needs
+	struct State {
+		config: Config,
+		/// The loaded error pages, by status
+		error_pages: RwLock<HashMap<StatusCode, ErrorPage>>,
+		/// The loaded error pages of each `vhost`, by status
+		vhost_error_pages: RwLock<Vec<HashMap<StatusCode, ErrorPage>>>,
+		/// The preloaded route files, by path
+		preloaded: RwLock<HashMap<PathBuf, PreloadedFile>>,
+		rate_limiter: Option<RateLimiter>,
+		shared: Arc<Shared>,
+	}
+
+	/// The state of a server that is kept across config reloads
+	#[derive(Default)]
+	struct Shared {
+		/// The clients waiting for file changes (only with `--watch` or `--dev`)
+		file_events: Option<FileEvents>,
+		/// The handler passed to `serve_with_fallback` (if any)
+		fallback: Option<FallbackHandler>,
+		/// The counters for the `metrics_path`
+		metrics: Metrics,
+	}
+
+	impl State {
+		async fn load(config: Config, shared: Arc<Shared>) -> Self {
+			let error_pages = load_error_pages(&config.error_pages).await;
+			if !config.error_pages.contains_key(&StatusCode::NOT_FOUND) {
+				log::info("proceeding without 404 file");
+			}
+			let mut vhost_error_pages = vec![];
+			for vhost in &config.vhosts {
+				vhost_error_pages.push(load_error_pages(&vhost.error_pages).await);
+			}
+			let preloaded = if config.preload {
+				preload(&config).await
+			} else {
+				HashMap::new()
+			};
+			let rate_limiter = config.rate_limit.map(RateLimiter::new);
+			Self {
+				config,
+				error_pages: RwLock::new(error_pages),
+				vhost_error_pages: RwLock::new(vhost_error_pages),
+				preloaded: RwLock::new(preloaded),
+				rate_limiter,
+				shared,
+			}
+		}
+
+		/// Drop everything that was loaded from the file at `path` into memory,
+		/// reloading it if it is an error page.
+		async fn invalidate(&self, path: &Path) {
+			self.preloaded.write().unwrap().remove(path);
+
+			let changed = changed_error_pages(&self.config.error_pages, path);
+			let reloaded = load_error_pages(&changed).await;
+			replace_error_pages(&mut self.error_pages.write().unwrap(), &changed, reloaded);
+			for (i, vhost) in self.config.vhosts.iter().enumerate() {
+				let changed = changed_error_pages(&vhost.error_pages, path);
+				let reloaded = load_error_pages(&changed).await;
+				let mut pages = self.vhost_error_pages.write().unwrap();
+				replace_error_pages(&mut pages[i], &changed, reloaded);
+			}
+		}
+	}
+
+	/// The error pages of `routes` whose file is the one at `path`
+	fn changed_error_pages(
+		routes: &HashMap<StatusCode, Route>,
+		path: &Path,
+	) -> HashMap<StatusCode, Route> {
+		(routes.iter())
+			.filter(|(_, r)| r.source == ContentSource::Disk && r.path == path)
+			.map(|(status, r)| (*status, r.clone()))
+			.collect()
+	}
+
+	/// Replace the `changed` error pages in `pages` with the `reloaded` ones
+	/// (removing those that failed to load)
+	fn replace_error_pages(
+		pages: &mut HashMap<StatusCode, ErrorPage>,
+		changed: &HashMap<StatusCode, Route>,
+		mut reloaded: HashMap<StatusCode, ErrorPage>,
+	) {
+		for status in changed.keys() {
+			match reloaded.remove(status) {
+				Some(page) => pages.insert(*status, page),
+				None => pages.remove(status),
+			};
+		}
+	}
+
+	/// The URL path that browsers request icons from on their own
+	const FAVICON_PATH: &str = "/favicon.ico";
+
+	/// The built-in `default_favicon` (a single transparent pixel)
+	#[rustfmt::skip]
+	const FAVICON: &[u8] = &[
+		// header: 1 image
+		0, 0, 1, 0, 1, 0,
+		// directory entry: 1x1 pixels, 32 bits per pixel, 48 bytes at offset 22
+		1, 1, 0, 0, 1, 0, 32, 0, 48, 0, 0, 0, 22, 0, 0, 0,
+		// bitmap header (with the height doubled for the AND mask)
+		40, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1, 0, 32, 0, 0, 0, 0, 0,
+		0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+		// the pixel (BGRA) and the AND mask (padded to 4 bytes)
+		0, 0, 0, 0, 0, 0, 0, 0,
+	];
+
+	/// The URL path of the server-sent events that tell browsers to reload (in `--dev` mode)
+	const LIVE_RELOAD_PATH: &str = "/__live-reload";
+
+	/// The script that is injected into HTML pages in `--dev` mode (listening to `LIVE_RELOAD_PATH`)
+	const LIVE_RELOAD_SCRIPT: &str = r#"<script>new EventSource("/__live-reload").onmessage = () => location.reload();</script>"#;
+
+	/// A handler for requests that don't match any route (see [`serve_with_fallback`]).
+	///
+	/// It can answer a request with `Some` response, or return `None` to leave it to
+	/// `default_favicon`, `fallback` or the 404 response.
+	pub type FallbackHandler = Arc<
+		dyn Fn(
+				Request<Body>,
+			) -> Pin<Box<dyn Future<Output = Option<axum::response::Response>> + Send>>
+			+ Send
+			+ Sync,
+	>;
+
+	/// The clients waiting for file changes
+	struct FileEvents {
+		/// The changed paths (relative to the config file's directory)
+		changes: broadcast::Sender<Arc<str>>,
+		/// Ends all event streams, since the server can't shut down while they're open
+		shutdown: CancellationToken,
+	}
+
+	impl FileEvents {
+		/// Tell all clients that the file at `path` changed
+		fn notify(&self, path: &str) {
+			// this only fails if no client is connected
+			let _ = self.changes.send(path.into());
+		}
+
+		/// A stream of events with the paths of changed files
+		fn events(&self) -> Response {
+			let mut changes = self.changes.subscribe();
+			let shutdown = self.shutdown.clone();
+			let (events, mut writer) = tokio::io::duplex(1024);
+			tokio::spawn(async move {
+				loop {
+					let path = tokio::select! {
+						change = changes.recv() => match change {
+							Ok(path) => path,
+							// some changes were missed, so just tell the client that something changed
+							Err(broadcast::error::RecvError::Lagged(_)) => "".into(),
+							Err(broadcast::error::RecvError::Closed) => return,
+						},
+						() = shutdown.cancelled() => return,
+					};
+					// every line needs its own `data` field, which the client joins with newlines
+					let mut event = String::new();
+					for line in path.split('\n') {
+						let _ = writeln!(event, "data: {}", line.trim_end_matches('\r'));
+					}
+					event.push('\n');
+					// this fails once the client has disconnected
+					if writer.write_all(event.as_bytes()).await.is_err() {
+						return;
+					}
+				}
+			});
+			Response::EventStream(events)
+		}
+	}
+
+	fn is_html(mime: &Mime) -> bool {
+		mime.type_() == mime::TEXT && mime.subtype() == mime::HTML
+	}
+
+	/// Insert the live reload script before the (last) closing body tag of `html`, or append it
+	fn inject_live_reload(html: &[u8]) -> Bytes {
+		let pos = html
+			.windows(b"</body>".len())
+			.rposition(|w| w.eq_ignore_ascii_case(b"</body>"))
+			.unwrap_or(html.len());
+		let mut injected = Vec::with_capacity(html.len() + LIVE_RELOAD_SCRIPT.len());
+		injected.extend_from_slice(&html[..pos]);
+		injected.extend_from_slice(LIVE_RELOAD_SCRIPT.as_bytes());
+		injected.extend_from_slice(&html[pos..]);
+		injected.into()
+	}
+
+	/// Counters for the `metrics_path` (only updated if it is set)
+	#[derive(Default)]
+	struct Metrics {
+		requests: AtomicU64,
+		/// The number of responses by status class (1xx to 5xx)
+		responses: [AtomicU64; 5],
+		bytes: AtomicU64,
+		/// The number of served requests for each route, by its URL pattern
+		/// (which are bounded by the config, unlike the requested URLs)
+		route_hits: Mutex<BTreeMap<String, u64>>,
+	}
+
+	impl Metrics {
+		fn record(&self, status: StatusCode, bytes: u64) {
+			self.requests.fetch_add(1, Ordering::Relaxed);
+			if let Some(counter) = self.responses.get(usize::from(status.as_u16() / 100) - 1) {
+				counter.fetch_add(1, Ordering::Relaxed);
+			}
+			self.bytes.fetch_add(bytes, Ordering::Relaxed);
+		}
+
+		fn hit(&self, pattern: &str) {
+			let mut route_hits = self.route_hits.lock().unwrap_or_else(|e| e.into_inner());
+			*route_hits.entry(pattern.to_string()).or_default() += 1;
+		}
+
+		/// Render the metrics in the Prometheus text format
+		fn render(&self) -> String {
+			let mut out = String::new();
+			let mut metric = |name: &str, help: &str, values: &[(String, u64)]| {
+				let _ = writeln!(out, "# HELP simple_http_server_{name} {help}");
+				let _ = writeln!(out, "# TYPE simple_http_server_{name} counter");
+				for (labels, value) in values {
+					let _ = writeln!(out, "simple_http_server_{name}{labels} {value}");
+				}
+			};
+			metric(
+				"requests_total",
+				"The number of handled requests.",
+				&[(String::new(), self.requests.load(Ordering::Relaxed))],
+			);
+			let responses: Vec<_> = self
+				.responses
+				.iter()
+				.enumerate()
+				.map(|(i, c)| {
+					let labels = format!("{{class=\"{}xx\"}}", i + 1);
+					(labels, c.load(Ordering::Relaxed))
+				})
+				.collect();
+			metric(
+				"responses_total",
+				"The number of responses by status class.",
+				&responses,
+			);
+			metric(
+				"sent_bytes_total",
+				"The number of bytes sent in response bodies.",
+				&[(String::new(), self.bytes.load(Ordering::Relaxed))],
+			);
+			let route_hits = self.route_hits.lock().unwrap_or_else(|e| e.into_inner());
+			let route_hits: Vec<_> = route_hits
+				.iter()
+				.map(|(route, hits)| {
+					let route = route
+						.replace('\\', "\\\\")
+						.replace('"', "\\\"")
+						.replace('\n', "\\n");
+					(format!("{{route=\"{route}\"}}"), *hits)
+				})
+				.collect();
+			metric(
+				"route_hits_total",
+				"The number of successfully served requests for each route, by its URL pattern.",
+				&route_hits,
+			);
+			out
+		}
+	}
+
+	/// A token bucket per client IP address
+	struct RateLimiter {
+		/// The tokens that are added to each bucket per second
+		rate: f64,
+		/// The maximum number of tokens in a bucket
+		burst: f64,
+		buckets: Mutex<HashMap<IpAddr, Bucket>>,
+	}
+
+	struct Bucket {
+		tokens: f64,
+		updated: Instant,
+	}
+
+	impl RateLimiter {
+		/// The number of buckets above which full ones are removed
+		const MAX_BUCKETS: usize = 10_000;
+
+		fn new(config: RateLimitConfig) -> Self {
+			let rate = config.requests_per_second.get();
+			Self {
+				rate: rate.into(),
+				burst: config.burst.map_or(rate, |b| b.get()).into(),
+				buckets: Mutex::new(HashMap::new()),
+			}
+		}
+
+		/// Take a token from the bucket of `ip`,
+		/// or return how long it takes until there is one again
+		fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+			let now = Instant::now();
+			let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+			if buckets.len() > Self::MAX_BUCKETS {
+				buckets.retain(|_, b| self.refill(b, now) < self.burst);
+			}
+			let bucket = buckets.entry(ip).or_insert(Bucket {
+				tokens: self.burst,
+				updated: now,
+			});
+			if self.refill(bucket, now) < 1.0 {
+				return Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate));
+			}
+			bucket.tokens -= 1.0;
+			Ok(())
+		}
+
+		/// Add the tokens since the last update to `bucket`
+		fn refill(&self, bucket: &mut Bucket, now: Instant) -> f64 {
+			let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+			bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+			bucket.updated = now;
+			bucket.tokens
+		}
+	}
+
+	/// Watch the config file and reload `state` whenever it changes.
+	///
+	/// The returned watcher has to be kept alive for this to work.
+	fn watch_config(args: Args, state: Arc<ArcSwap<State>>) -> Option<RecommendedWatcher> {
+		let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+		let file_name = args.config.file_name().map(|n| n.to_os_string());
+		let mut watcher = match notify::recommended_watcher(move |event: notify::Result<Event>| {
+			let Ok(event) = event else { return };
+			let relevant = matches!(
+				event.kind,
+				EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+			);
+			if relevant
+				&& event
+					.paths
+					.iter()
+					.any(|p| p.file_name() == file_name.as_deref())
+			{
+				let _ = tx.send(());
+			}
+		}) {
+			Ok(watcher) => watcher,
+			Err(e) => {
+				log::error(format_args!("failed to watch the config file ({e})"));
+				return None;
+			}
+		};
+		// watch the directory, since editors often replace the file instead of writing to it
+		let dir = match args.config.parent() {
+			Some(dir) if dir != Path::new("") => dir,
+			_ => Path::new("."),
+		};
+		if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+			log::error(format_args!("failed to watch the config file ({e})"));
+			return None;
+		}
+
+		tokio::spawn(async move {
+			while rx.recv().await.is_some() {
+				// a single save often causes several events
+				tokio::time::sleep(Duration::from_millis(100)).await;
+				while rx.try_recv().is_ok() {}
+				reload_config(&args, &state).await;
+			}
+		});
+		Some(watcher)
+	}
+
+	/// Watch everything in the config file's directory, log changes and invalidate the changed files in `state`.
+	///
+	/// Changes to the config file and the files written by the server (e.g. `log_file`) are ignored.
+	fn watch_files(state: Arc<ArcSwap<State>>) -> Option<RecommendedWatcher> {
+		let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+		let dir = state.load().config.file_dir.clone();
+		let mut watcher = match notify::recommended_watcher(move |event: notify::Result<Event>| {
+			let Ok(event) = event else { return };
+			if matches!(
+				event.kind,
+				EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+			) {
+				for path in event.paths {
+					let _ = tx.send(path);
+				}
+			}
+		}) {
+			Ok(watcher) => watcher,
+			Err(e) => {
+				log::error(format_args!("failed to watch the served files ({e})"));
+				return None;
+			}
+		};
+		if let Err(e) = watcher.watch(&dir, RecursiveMode::Recursive) {
+			log::error(format_args!("failed to watch the served files ({e})"));
+			return None;
+		}
+		log::info(format_args!("watching {dir:?} for changes"));
+
+		tokio::spawn(async move {
+			while let Some(path) = rx.recv().await {
+				// a single save often causes several events
+				tokio::time::sleep(Duration::from_millis(100)).await;
+				let mut paths = vec![path];
+				while let Ok(path) = rx.try_recv() {
+					paths.push(path);
+				}
+				paths.sort();
+				paths.dedup();
+
+				let state = state.load_full();
+				let config = &state.config;
+				// config changes are handled by `watch_config`
+				let ignored: Vec<_> = std::iter::once(config.args.config.clone())
+					.chain(
+						[
+							&config.log_file,
+							&config.access_log_file,
+							&config.bound_addr_file,
+						]
+						.into_iter()
+						.flatten()
+						.map(|f| config.file_dir.join(f.as_std_path())),
+					)
+					.filter_map(|f| std::fs::canonicalize(f).ok())
+					.collect();
+				for path in paths {
+					if std::fs::canonicalize(&path).is_ok_and(|p| ignored.contains(&p)) {
+						continue;
+					}
+					let log_path = path.strip_prefix(&dir).unwrap_or(&path);
+					log::info(format_args!("{log_path:?} changed"));
+					state.invalidate(&path).await;
+					if let Some(events) = &state.shared.file_events {
+						events.notify(&event_path(log_path));
+					}
+				}
+			}
+		});
+		Some(watcher)
+	}
+
+	/// `path` (relative to the config file's directory) with `/` as the separator, for the file change events
+	fn event_path(path: &Path) -> String {
+		let components = path.components().map(|c| c.as_os_str().to_string_lossy());
+		components.collect::<Vec<_>>().join("/")
+	}
+
+	async fn reload_config(args: &Args, state: &ArcSwap<State>) {
+		let config = match Config::new(args.clone()) {
+			Ok(config) => config,
+			Err(e) => {
+				log::error(format_args!(
+					"failed to reload config, keeping the old one: {e}"
+				));
+				return;
+			}
+		};
+		let old = state.load();
+		if config.addr != old.config.addr
+			|| config.failsafe_addrs != old.config.failsafe_addrs
+			|| config.bind_all != old.config.bind_all
+			|| config.bound_addr_file != old.config.bound_addr_file
+			|| config.max_requests != old.config.max_requests
+			|| config.tls != old.config.tls
+			|| config.socket != old.config.socket
+			|| config.http != old.config.http
+			|| (config.tls.is_some() && config.http2 != old.config.http2)
+		{
+			log::warn(
+				"changes to `addr`, `failsafe_addrs`, `bind_all`, `bound_addr_file`, `max_requests`, `socket`, `http` and `tls` (including `http2` with TLS) only take effect after a restart",
+			);
+		}
+		config.setup_log();
+		let shared = old.shared.clone();
+		state.store(Arc::new(State::load(config, shared.clone()).await));
+		log::info("reloaded config");
+		if let (Some(events), Some(name)) = (&shared.file_events, args.config.file_name()) {
+			events.notify(&event_path(Path::new(name)));
+		}
+	}
+
+	async fn app(state: &State, request: Request<Body>) -> axum::response::Response {
+		let start = Instant::now();
+		let client = client_ip(&state.config, &request);
+		let scheme = request_scheme(&state.config, request.headers());
+		let mut log = log::RequestLog::new(&request, client, scheme);
+		let vhost = request_host(request.uri(), request.headers())
+			.and_then(|h| state.config.virtual_host(h.host()));
+		let origin = request.headers().get(ORIGIN).cloned();
+		let preflight = log.method == Method::OPTIONS
+			&& request
+				.headers()
+				.contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+		let mut note = String::new();
+		let response = match state.config.request_timeout_secs {
+			Some(secs) => {
+				let handled = handle(state, request, client, vhost, &mut note);
+				match tokio::time::timeout(Duration::from_secs(secs), handled).await {
+					Ok(response) => response,
+					Err(_) => {
+						note = format!("timed out after {secs}s");
+						Response::PureCode(StatusCode::REQUEST_TIMEOUT)
+					}
+				}
+			}
+			None => handle(state, request, client, vhost, &mut note).await,
+		};
+		let response = {
+			let vhost_pages = state.vhost_error_pages.read().unwrap();
+			match vhost.map(|i| &vhost_pages[i]) {
+				Some(pages) if pages.contains_key(&response.status()) => {
+					response.with_error_page(pages)
+				}
+				_ => response.with_error_page(&state.error_pages.read().unwrap()),
+			}
+		};
+		let response = match state.config.args.dev {
+			true => response.with_live_reload(),
+			false => response,
+		};
+		let mut response = match log.method {
+			Method::HEAD => response.into_head(),
+			_ => response,
+		}
+		.into_response();
+		// these are only added here so that all other headers take priority
+		for (name, value) in &state.config.default_headers {
+			if !response.headers().contains_key(name) {
+				response.headers_mut().insert(name, value.clone());
+			}
+		}
+		// unlike `default_headers`, this replaces the header of a route's `headers`
+		match (
+			&state.config.server_header_value,
+			&state.config.server_header,
+		) {
+			(Some(value), _) => {
+				response.headers_mut().insert(SERVER, value.clone());
+			}
+			(None, Some(ServerHeader::Enabled(false))) => {
+				response.headers_mut().remove(SERVER);
+			}
+			(None, _) => {}
+		}
+		// without a `Content-Type`, browsers have to sniff the type to display the response at all
+		if state.config.nosniff != Some(false)
+			&& response.headers().contains_key(CONTENT_TYPE)
+			&& !response.headers().contains_key(X_CONTENT_TYPE_OPTIONS)
+		{
+			(response.headers_mut())
+				.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+		}
+		if let (Some(cors), Some(origin)) = (&state.config.cors, origin) {
+			add_cors_headers(cors, origin, preflight, response.headers_mut());
+		}
+
+		let bytes = match log.method {
+			Method::HEAD => 0,
+			_ => response
+				.headers()
+				.get(CONTENT_LENGTH)
+				.and_then(|v| v.to_str().ok()?.parse().ok())
+				.unwrap_or(0),
+		};
+		if state.config.metrics_path.is_some() {
+			state.shared.metrics.record(response.status(), bytes);
+		}
+		let status = response.status().as_u16();
+		// browsers request this on their own, so it being missing isn't worth an info message
+		let level = match status == 404 && log.uri.path() == FAVICON_PATH {
+			true => LogLevel::Debug,
+			false => LogLevel::Info,
+		};
+		match (
+			status,
+			state.config.log_404,
+			state.config.log_404_summary_secs,
+		) {
+			(404, Some(false), _) => {}
+			(404, _, Some(secs)) => log::count_not_found(Duration::from_secs(secs.get())),
+			_ => {
+				log.status = status;
+				log.bytes = bytes;
+				log.duration = start.elapsed();
+				log.note = note;
+				log::request(level, &log);
+			}
+		}
+		response
+	}
+
+	/// The address of the client that sent `request`.
+	///
+	/// With `trust_proxy`, this is taken from `X-Forwarded-For`: the address `trusted_proxy_hops` from the end
+	/// (the one that the first trusted proxy received the request from), or the leftmost one if that isn't set.
+	fn client_ip(config: &Config, request: &Request<Body>) -> Option<IpAddr> {
+		if config.trust_proxy {
+			let forwarded: Vec<_> = (request.headers().get_all("x-forwarded-for").iter())
+				.filter_map(|v| v.to_str().ok())
+				.flat_map(|v| v.split(','))
+				.collect();
+			let index = match config.trusted_proxy_hops {
+				Some(hops) => forwarded.len().saturating_sub(hops.get()),
+				None => 0,
+			};
+			if let Some(ip) = forwarded.get(index).and_then(|ip| ip.trim().parse().ok()) {
+				return Some(ip);
+			}
+		}
+		// the peer address is only known for TCP connections
+		let ConnectInfo(addr) = request.extensions().get::<ConnectInfo<SocketAddr>>()?;
+		Some(addr.ip())
+	}
+
+	/// The scheme that the client used for a request with `headers`.
+	///
+	/// With `trust_proxy`, this is taken from `X-Forwarded-Proto` (if present), otherwise it is the one of this server.
+	fn request_scheme(config: &Config, headers: &HeaderMap) -> &'static str {
+		if config.trust_proxy {
+			let forwarded = headers
+				.get("x-forwarded-proto")
+				.and_then(|v| v.to_str().ok());
+			// with several proxies, the first one is the one that the client connected to
+			match forwarded.and_then(|v| v.split(',').next()).map(str::trim) {
+				Some(s) if s.eq_ignore_ascii_case("https") => return "https",
+				Some(s) if s.eq_ignore_ascii_case("http") => return "http",
+				_ => {}
+			}
+		}
+		match config.tls.is_some() {
+			true => "https",
+			false => "http",
+		}
+	}
+
+	/// Add the CORS headers for a request from `origin` (if it is allowed)
+	fn add_cors_headers(
+		cors: &CorsPolicy,
+		origin: HeaderValue,
+		preflight: bool,
+		headers: &mut HeaderMap,
+	) {
+		let allow_origin = match &cors.origins {
+			None => HeaderValue::from_static("*"),
+			Some(origins) => {
+				// the response depends on the origin, so caches must not mix them up
+				headers.append(VARY, HeaderValue::from_static("Origin"));
+				if !origins.contains(&origin) {
+					return;
+				}
+				origin
+			}
+		};
+		headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+		if preflight {
+			headers.insert(ACCESS_CONTROL_ALLOW_METHODS, cors.methods.clone());
+			if let Some(allowed) = &cors.headers {
+				headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed.clone());
+			}
+			if let Some(max_age) = &cors.max_age {
+				headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.clone());
+			}
+		}
+	}
+
+	/// The host that a request with `uri` and `headers` is for (from the URI in HTTP/2 or the `Host` header)
+	fn request_host(uri: &Uri, headers: &HeaderMap) -> Option<Authority> {
+		uri.authority().cloned().or_else(|| {
+			let host = headers.get(HOST)?.to_str().ok()?;
+			Authority::from_str(host).ok()
+		})
+	}
+
+	/// Handle a request, describing what was done in `note` (for the log)
+	async fn handle(
+		state: &State,
+		request: Request<Body>,
+		client: Option<IpAddr>,
+		vhost: Option<usize>,
+		note: &mut String,
+	) -> Response {
+		if request.version() == Version::HTTP_2 && state.config.http2 == Some(false) {
+			*note = "blocked (HTTP/2 is disabled)".into();
+			return Response::PureCode(StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+		}
+		if let Some(max) = state.config.max_request_bytes {
+			let len = request
+				.headers()
+				.get(CONTENT_LENGTH)
+				.and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+			if let Some(len) = len.filter(|&len| len > max) {
+				*note = format!("blocked (body of {len} bytes exceeds {max} bytes)");
+				return Response::PureCode(StatusCode::PAYLOAD_TOO_LARGE);
+			}
+		}
+		// health checks bypass everything else, including authentication and rate limiting
+		let method = request.method();
+		if matches!(*method, Method::GET | Method::HEAD)
+			&& state.config.is_health_path(request.uri().path())
+		{
+			*note = "health check".into();
+			let response = Response::MimeBody(
+				StatusCode::OK,
+				Some(SetMime(mime::TEXT_PLAIN_UTF_8)),
+				Bytes::from_static(b"OK"),
+			);
+			return if method == Method::HEAD {
+				response.into_head()
+			} else {
+				response
+			};
+		}
+		if !state.config.allowed_hosts.is_empty() {
+			let host = request_host(request.uri(), request.headers());
+			match &host {
+				Some(host) if state.config.is_allowed_host(host.host()) => {}
+				Some(host) => {
+					*note = format!("blocked (host {:?} is not allowed)", host.host());
+					return Response::PureCode(StatusCode::MISDIRECTED_REQUEST);
+				}
+				None => {
+					*note = "blocked (missing host)".into();
+					return Response::PureCode(StatusCode::MISDIRECTED_REQUEST);
+				}
+			}
+		}
+		if let Some(events) = &state.shared.file_events {
+			let path = request.uri().path();
+			if method == Method::GET
+				&& ((state.config.args.dev && path == LIVE_RELOAD_PATH)
+					|| state.config.is_events_path(path))
+			{
+				*note = "file change events".into();
+				return events.events();
+			}
+		}
+		if let (Some(limiter), Some(client)) = (&state.rate_limiter, client) {
+			if let Err(wait) = limiter.check(client) {
+				*note = "blocked (rate limit exceeded)".into();
+				let retry_after = wait.as_secs_f64().ceil() as u64;
+				return Response::PureCode(StatusCode::TOO_MANY_REQUESTS)
+					.with_header(RETRY_AFTER, HeaderValue::from(retry_after));
+			}
+		}
+		if matches!(*method, Method::GET | Method::HEAD)
+			&& state.config.is_metrics_path(request.uri().path())
+		{
+			*note = "metrics".into();
+			let response = Response::MimeBody(
+				StatusCode::OK,
+				Some(SetMime(mime::TEXT_PLAIN_UTF_8)),
+				state.shared.metrics.render().into(),
+			);
+			return if method == Method::HEAD {
+				response.into_head()
+			} else {
+				response
+			};
+		}
+		if matches!(*method, Method::GET | Method::HEAD)
+			&& state.config.is_routes_path(request.uri().path())
+		{
+			*note = "routes".into();
+			let response = Response::MimeBody(
+				StatusCode::OK,
+				Some(SetMime(mime::APPLICATION_JSON)),
+				routes_json(state.config.route_table(vhost)).into(),
+			);
+			return if method == Method::HEAD {
+				response.into_head()
+			} else {
+				response
+			};
+		}
+		let request = match &state.shared.fallback {
+			Some(handler) if is_unrouted(&state.config, vhost, request.uri().path()) => {
+				let config = &state.config;
+				if config.auth.as_ref().is_some_and(|a| a.all_routes)
+					&& !authorized(config, request.headers())
+				{
+					return unauthorized(config, note);
+				}
+				let (parts, body) = request.into_parts();
+				if let Some(response) = handler(Request::from_parts(parts.clone(), body)).await {
+					*note = "fallback handler".into();
+					return Response::Custom(response);
+				}
+				// the handler got the body, but it isn't needed for the remaining methods
+				Request::from_parts(parts, Body::empty())
+			}
+			_ => request,
+		};
+		let head = match *request.method() {
+			Method::GET => false,
+			Method::HEAD => true,
+			Method::OPTIONS => return options(&state.config, vhost, request.uri(), note),
+			_ => {
+				// the server can only handle GET, HEAD and OPTIONS requests
+				*note = "unsupported method".into();
+				return Response::Allow(StatusCode::METHOD_NOT_ALLOWED);
+			}
+		};
+
+		let (request, _) = request.into_parts();
+		let response = get(state, vhost, &request, head, note).await;
+		if head {
+			response.into_head()
+		} else {
+			response
+		}
+	}
+
+	/// Whether a request for `path` matches neither a route nor a redirect
+	/// (so that it is passed to the `FallbackHandler`)
+	fn is_unrouted(config: &Config, vhost: Option<usize>, path: &str) -> bool {
+		config.resolve_route(vhost, path).is_none()
+			&& config.resolve_redirect(path).is_none()
+			&& config.trailing_slash_redirect(vhost, path).is_none()
+	}
+
+	/// The list of routes for the `routes_path`, without their files.
+	///
+	/// Wildcard and directory routes are listed with their pattern or prefix.
+	fn routes_json(table: &RouteTable) -> String {
+		let mime = |route: &Route| route.mime.as_ref().map(|m| m.to_string());
+		let mut routes: Vec<_> = table.exact.iter().collect();
+		routes.sort_by_key(|(url, _)| *url);
+		let exact = routes.into_iter().map(|(url, route)| {
+			serde_json::json!({ "url": format!("/{url}"), "kind": "exact", "mime": mime(route) })
+		});
+		let wildcard = table.globs.iter().map(|g| {
+			let url = format!("/{}*{}", g.prefix, g.suffix);
+			serde_json::json!({ "url": url, "kind": "wildcard", "mime": mime(&g.route) })
+		});
+		let directory = table.dirs.iter().map(|(prefix, route)| {
+			serde_json::json!({ "url": format!("/{prefix}"), "kind": "directory", "mime": mime(route) })
+		});
+		serde_json::Value::Array(exact.chain(wildcard).chain(directory).collect()).to_string()
+	}
+
+	/// Handle an OPTIONS request.
+	///
+	/// The supported methods are the same for all routes,
+	/// so this only needs to check whether the route exists.
+	fn options(config: &Config, vhost: Option<usize>, uri: &Uri, note: &mut String) -> Response {
+		// `OPTIONS *` asks about the server in general
+		let url = uri.path();
+		if uri == "*"
+			|| config.resolve_route(vhost, url).is_some()
+			|| config.resolve_redirect(url).is_some()
+		{
+			*note = "allowed methods".into();
+			Response::Allow(StatusCode::NO_CONTENT)
+		} else {
+			*note = "blocked (no configured route)".into();
+			Response::PureCode(StatusCode::NOT_FOUND)
+		}
+	}
+
+	/// Handle a GET request, or a HEAD request if `head` is set.
+	async fn get(
+		state: &State,
+		vhost: Option<usize>,
+		request: &Parts,
+		head: bool,
+		note: &mut String,
+	) -> Response {
+		use std::io::ErrorKind;
+
+		let config = &state.config;
+		let uri = &request.uri;
+
+		if let Some(redirect) = config.resolve_redirect(uri.path()) {
+			let location = match redirect.location.to_str() {
+				Ok(path) if path.starts_with('/') => header_value(location(config, request, path)),
+				_ => redirect.location.clone(),
+			};
+			*note = format!("redirect to {location:?}");
+			let status = if redirect.permanent {
+				StatusCode::MOVED_PERMANENTLY
+			} else {
+				StatusCode::FOUND
+			};
+			return Response::PureCode(status).with_header(LOCATION, location);
+		}
+		if let Some(path) = config.trailing_slash_redirect(vhost, uri.path()) {
+			return trailing_slash_redirect(config, request, path, note);
+		}
+
+		let route = config.resolve_route(vhost, uri.path());
+		let favicon = config
+			.favicon
+			.as_ref()
+			.filter(|_| uri.path() == FAVICON_PATH);
+		let route = match (route, favicon, &config.fallback) {
+			(Some(route), ..) => route,
+			(None, Some(Favicon::Builtin), _) => {
+				*note = "built-in favicon".into();
+				let mime = SetMime("image/x-icon".parse().unwrap());
+				let response =
+					Response::MimeBody(StatusCode::OK, Some(mime), Bytes::from_static(FAVICON));
+				return if head { response.into_head() } else { response };
+			}
+			(None, Some(Favicon::File(route)), _) => Cow::Borrowed(&**route),
+			(None, None, Some(fallback)) if accepts_html(&request.headers) => {
+				Cow::Borrowed(fallback)
+			}
+			(None, ..) => {
+				*note = "blocked (no configured route)".into();
+				return Response::PureCode(StatusCode::NOT_FOUND);
+			}
+		};
+		// the alternatives don't know the pattern they were configured under
+		let pattern = route.pattern.clone();
+		let negotiated = !route.alternatives.is_empty();
+		let route = match negotiated {
+			false => route,
+			true => match negotiate(&request.headers, &route.alternatives) {
+				Some(alternative) => Cow::Owned(alternative.clone()),
+				None => {
+					*note = "no acceptable alternative".into();
+					return Response::PureCode(StatusCode::NOT_ACCEPTABLE).with_vary("Accept");
+				}
+			},
+		};
+		let path = &route.path;
+
+		if config.requires_auth(&route) && !authorized(config, &request.headers) {
+			return unauthorized(config, note);
+		}
+
+		let log_path = path.strip_prefix(&config.file_dir).unwrap_or(path);
+		*note = format!("open {:?}", log_path);
+
+		let response = match serve_file(state, &route, request, head).await {
+			Ok(mut r) => {
+				let served = r.status().is_success() || r.status() == StatusCode::NOT_MODIFIED;
+				if served && config.metrics_path.is_some() && !pattern.is_empty() {
+					state.shared.metrics.hit(&pattern);
+				}
+				if let Some(c) = &route.cache_control {
+					if r.status().is_success() || r.status() == StatusCode::NOT_MODIFIED {
+						r = r.with_header(CACHE_CONTROL, c.clone());
+					}
+				}
+				if r.status().is_success() {
+					if route.download {
+						let name = match &route.filename {
+							Some(name) => Some(name.as_str()),
+							None => path.file_name().and_then(|n| n.to_str()),
+						};
+						r = r.with_header(CONTENT_DISPOSITION, content_disposition(name));
+					}
+					r = r.with_headers(&route.headers);
+				}
+				r
+			}
+			Err(e)
+				if e.kind() == ErrorKind::NotFound
+					&& route.dir_index
+					&& route.source == ContentSource::Disk
+					&& config.directory_listing =>
+			{
+				let dir = path.parent().unwrap_or(path);
+				match directory_listing(dir, uri.path()).await {
+					Ok(page) => Response::MimeBody(
+						StatusCode::OK,
+						Some(SetMime(mime::TEXT_HTML_UTF_8)),
+						page.into(),
+					),
+					Err(e) => {
+						log::error(format_args!("failed to list {dir:?}: {e}"));
+						io_error_response(&e)
+					}
+				}
+			}
+			Err(_)
+				if route.source == ContentSource::Disk
+					&& tokio::fs::metadata(path).await.is_ok_and(|m| m.is_dir()) =>
+			{
+				// serving the index under both URLs would break its relative links, so this always redirects
+				if config.trailing_slash != TrailingSlash::Strict && !uri.path().ends_with('/') {
+					let path = format!("{}/", uri.path());
+					return trailing_slash_redirect(config, request, path, note);
+				}
+				// this isn't an I/O error, just a route (or request) to a directory instead of a file
+				log::warn(format_args!("{log_path:?} is a directory"));
+				*note = format!("blocked ({log_path:?} is a directory)");
+				Response::PureCode(StatusCode::NOT_FOUND)
+			}
+			Err(e) => {
+				log::error(format_args!("I/O error at {path:?}: {e}"));
+				io_error_response(&e)
+			}
+		};
+		match negotiated {
+			true => response.with_vary("Accept"),
+			false => response,
+		}
+	}
+
+	/// Permanently redirect `request` to `path`, keeping its query
+	fn trailing_slash_redirect(
+		config: &Config,
+		request: &Parts,
+		path: String,
+		note: &mut String,
+	) -> Response {
+		let path = match request.uri.query() {
+			Some(query) => format!("{path}?{query}"),
+			None => path,
+		};
+		let location = location(config, request, &path);
+		*note = format!("redirect to {location:?} (trailing slash)");
+		Response::PureCode(StatusCode::MOVED_PERMANENTLY)
+			.with_header(LOCATION, header_value(location))
+	}
+
+	/// The `Location` for redirecting `request` to the server-relative `path`.
+	///
+	/// With `trust_proxy`, this is an absolute URL with the scheme from `X-Forwarded-Proto` (and the requested host),
+	/// so that clients stay on the scheme they used to reach the proxy.
+	fn location(config: &Config, request: &Parts, path: &str) -> String {
+		match request_host(&request.uri, &request.headers) {
+			Some(host) if config.trust_proxy && !path.starts_with("//") => {
+				format!(
+					"{}://{host}{path}",
+					request_scheme(config, &request.headers)
+				)
+			}
+			_ => path.to_string(),
+		}
+	}
+
+	/// The response to a request without valid credentials
+	fn unauthorized(config: &Config, note: &mut String) -> Response {
+		*note = "unauthorized".into();
+		let response = Response::PureCode(StatusCode::UNAUTHORIZED);
+		match &config.auth_challenge {
+			Some(challenge) => response.with_header(WWW_AUTHENTICATE, challenge.clone()),
+			None => response,
+		}
+	}
+
+	/// Whether `headers` contain valid credentials for the configured authentication
+	fn authorized(config: &Config, headers: &HeaderMap) -> bool {
+		let Some(auth) = &config.auth else {
+			return false;
+		};
+		let Some(credentials) = headers
+			.get(AUTHORIZATION)
+			.and_then(|v| v.to_str().ok())
+			.and_then(|v| v.split_once(' '))
+			.filter(|(scheme, _)| scheme.eq_ignore_ascii_case(auth.mode.scheme()))
+			.map(|(_, credentials)| credentials.trim())
+		else {
+			return false;
+		};
+
+		match auth.mode {
+			AuthMode::Basic => {
+				let credentials = BASE64_STANDARD
+					.decode(credentials)
+					.ok()
+					.and_then(|credentials| String::from_utf8(credentials).ok());
+				let Some((user, password)) = credentials.as_deref().and_then(|c| c.split_once(':'))
+				else {
+					return false;
+				};
+				auth.users.get(user).is_some_and(|expected| {
+					constant_time_eq(expected.as_bytes(), password.as_bytes())
+				})
+			}
+			AuthMode::Bearer => auth
+				.token
+				.as_ref()
+				.is_some_and(|token| constant_time_eq(token.as_bytes(), credentials.as_bytes())),
+		}
+	}
+
+	/// Compare `a` and `b` in a time that only depends on their lengths
+	fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+		a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+	}
+
+	/// The path and query of `uri`.
+	///
+	/// This excludes the scheme and authority, which are present e.g. in HTTP/2 requests.
+	#[cfg(feature = "tls")]
+	fn path_and_query(uri: &Uri) -> &str {
+		uri.path_and_query().map_or("/", |pq| pq.as_str())
+	}
+
+	/// The response for a failure to read a file
+	fn io_error_response(e: &std::io::Error) -> Response {
+		use std::io::ErrorKind;
+
+		match e.kind() {
+			ErrorKind::NotFound => Response::PureCode(StatusCode::NOT_FOUND),
+			ErrorKind::PermissionDenied => Response::PureCode(StatusCode::FORBIDDEN),
+			_ => Response::MimeBody(
+				StatusCode::INTERNAL_SERVER_ERROR,
+				Some(SetMime(mime::TEXT_PLAIN_UTF_8)),
+				// for security reasons, the client doesn't get the specific error
+				Bytes::from_static(b"I/O error"),
+			),
+		}
+	}
+
+	/// Generate an HTML page that lists the (non-hidden) entries of `dir`,
+	/// which is served at the URL path `url`.
+	async fn directory_listing(dir: &Path, url: &str) -> std::io::Result<String> {
+		let mut entries = vec![];
+		let mut read_dir = tokio::fs::read_dir(dir).await?;
+		while let Some(entry) = read_dir.next_entry().await? {
+			let name = entry.file_name().to_string_lossy().into_owned();
+			if name.starts_with('.') {
+				continue;
+			}
+			let is_dir = entry.file_type().await?.is_dir();
+			entries.push((name, is_dir));
+		}
+		entries.sort();
+
+		// links are absolute, since relative ones would break without a trailing slash
+		let base = html_escape(url.strip_suffix('/').unwrap_or(url));
+		let title = format!("Index of {base}/");
+		let mut page = format!(
+			"<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n<ul>\n"
+		);
+		for (name, is_dir) in entries {
+			let slash = if is_dir { "/" } else { "" };
+			let href = utf8_percent_encode(&name, PATH_SEGMENT);
+			let name = html_escape(&name);
+			page += &format!("<li><a href=\"{base}/{href}{slash}\">{name}{slash}</a></li>\n");
+		}
+		page += "</ul>\n</body>\n</html>\n";
+		Ok(page)
+	}
+
+	/// The characters that need to be percent-encoded in a path segment
+	/// (i.e. everything except unreserved characters, as per RFC 3986)
+	const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+		.remove(b'-')
+		.remove(b'.')
+		.remove(b'_')
+		.remove(b'~');
+
+	/// The characters that need to be percent-encoded in an extended parameter value
+	/// (i.e. everything except `attr-char`s, as per RFC 8187)
+	const EXT_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+		.remove(b'!')
+		.remove(b'#')
+		.remove(b'$')
+		.remove(b'&')
+		.remove(b'+')
+		.remove(b'-')
+		.remove(b'.')
+		.remove(b'^')
+		.remove(b'_')
+		.remove(b'`')
+		.remove(b'|')
+		.remove(b'~');
+
+	/// The `Content-Disposition` header value for downloading a file named `filename`.
+	///
+	/// Names that aren't printable ASCII are given as an encoded `filename*`,
+	/// with an ASCII approximation as `filename` for old clients.
+	fn content_disposition(filename: Option<&str>) -> HeaderValue {
+		let Some(name) = filename else {
+			return HeaderValue::from_static("attachment");
+		};
+		let ascii: String = name
+			.chars()
+			.map(|c| match c {
+				' '..='~' if c != '"' && c != '\\' => c,
+				_ => '_',
+			})
+			.collect();
+		let mut value = format!("attachment; filename=\"{ascii}\"");
+		if ascii != name {
+			let encoded = utf8_percent_encode(name, EXT_VALUE);
+			value += &format!("; filename*=UTF-8''{encoded}");
+		}
+		header_value(value)
+	}
+
+	fn html_escape(s: &str) -> String {
+		s.replace('&', "&amp;")
+			.replace('<', "&lt;")
+			.replace('>', "&gt;")
+			.replace('"', "&quot;")
+	}
+
+	/// Respond with the file of `route`, taking the request's conditional and content negotiation headers into account.
+	///
+	/// For HEAD requests, the file is only stat-ed instead of read
+	/// (unless it would be compressed, since then the length is only known afterwards).
+	async fn serve_file(
+		state: &State,
+		route: &Route,
+		request: &Parts,
+		head: bool,
+	) -> std::io::Result<Response> {
+		let config = &state.config;
+		let path = &route.path;
+		// the live reload script can only be injected into uncompressed pages
+		let live_reload = config.args.dev && route.mime.as_ref().is_some_and(is_html);
+		// templates are filled in for every response, so they are always read completely
+		// and have no validators (which wouldn't change with the `template_vars`)
+		let template = route.template && route.mime.as_ref().is_some_and(is_compressible);
+		let stream_threshold = config.stream_threshold.filter(|_| !template);
+
+		let ranges = request
+			.headers
+			.get(RANGE)
+			.and_then(|v| v.to_str().ok())
+			.and_then(parse_ranges)
+			.filter(|_| !template);
+		// ranges refer to the uncompressed file, so they take priority over (pre)compression
+		let check_precompressed = config.precompressed
+			&& route.source == ContentSource::Disk
+			&& ranges.is_none()
+			&& !live_reload
+			&& !template;
+		let precompressed = match check_precompressed {
+			true => open_precompressed(path, config.encodings(), &request.headers).await,
+			false => None,
+		};
+
+		let (content, precompressed) = match (precompressed, route.source) {
+			(Some((encoding, content)), _) => (content, Some(encoding)),
+			(None, ContentSource::Disk) => {
+				let preloaded = state.preloaded.read().unwrap().get(path).cloned();
+				match Content::open(path, preloaded.as_ref()).await {
+					Err(e)
+						if e.kind() == std::io::ErrorKind::NotFound
+							&& config.decompress_fallback =>
+					{
+						(Content::decompressed(path).await?, None)
+					}
+					content => (content?, None),
+				}
+			}
+			(None, ContentSource::Embedded) => (Content::embedded(path)?, None),
+		};
+
+		let modified = content.modified.filter(|_| !template);
+		let last_modified = modified.map(http_date);
+		// ranges are never (pre)compressed, so they refer to the uncompressed representation
+		// (which isn't sent as it is if the live reload script is injected)
+		let identity_etag = content.etag(None, live_reload).filter(|_| !template);
+		// if the file changed since the client got the rest of it, the ranges would be corrupt, so the whole file is sent
+		let ranges =
+			ranges.filter(|_| if_range_matches(&request.headers, identity_etag.as_ref(), modified));
+
+		// streamed files are never read into memory, so they can't be compressed
+		let compressible = config.compression
+			&& ranges.is_none()
+			&& !live_reload
+			&& !content.is_streamed(stream_threshold)
+			&& route.mime.as_ref().is_some_and(is_compressible);
+		// whether the response depends on `Accept-Encoding`, which caches need to know
+		let negotiated = (compressible || check_precompressed) && !config.encodings().is_empty();
+		let vary = |r: Response| match negotiated {
+			true => r.with_header(VARY, HeaderValue::from_static("Accept-Encoding")),
+			false => r,
+		};
+
+		let encoding = if compressible && precompressed.is_none() {
+			config
+				.encodings()
+				.iter()
+				.copied()
+				.find(|e| accepts_encoding(&request.headers, e.name()))
+		} else {
+			None
+		};
+		// every encoding is a different representation, so it needs its own ETag
+		let etag = match (encoding, precompressed) {
+			// compressing on the fly isn't guaranteed to give the same bytes every time
+			(Some(encoding), _) => content.etag(Some(encoding), true),
+			(None, Some(encoding)) => content.etag(Some(encoding), false),
+			(None, None) => identity_etag.clone(),
+		};
+		let etag = etag.filter(|_| !template);
+
+		let not_modified = || {
+			let r = vary(Response::PureCode(StatusCode::NOT_MODIFIED));
+			match &etag {
+				Some(etag) => r.with_header(ETAG, etag.clone()),
+				None => r,
+			}
+		};
+		// as per RFC 9110, `If-Modified-Since` is only evaluated without `If-None-Match`
+		if request.headers.contains_key(IF_NONE_MATCH) {
+			if etag
+				.as_ref()
+				.is_some_and(|etag| etag_matches(&request.headers, etag))
+			{
+				return Ok(not_modified());
+			}
+		} else if let Some(modified) = modified {
+			if modified_since(&request.headers, modified) == Some(false) {
+				return Ok(not_modified());
+			}
+		}
+
+		let mime = route.mime.clone().map(SetMime);
+		let response = content
+			.into_response(
+				mime,
+				ranges,
+				head && encoding.is_none() && !template,
+				stream_threshold,
+			)
+			.await?;
+		let response = match response {
+			Response::MimeBody(c, m, v) if template => {
+				Response::MimeBody(c, m, fill_template(&v, &config.template_vars))
+			}
+			r => r,
+		};
+		let (response, etag) = match (response, encoding) {
+			(Response::MimeBody(c, m, v), Some(encoding)) => match compress(&v, encoding) {
+				Ok(v) => {
+					let encoding = HeaderValue::from_static(encoding.name());
+					let response = Response::MimeBody(c, m, v.into());
+					(response.with_header(CONTENT_ENCODING, encoding), etag)
+				}
+				Err(e) => {
+					log::error(format_args!("failed to compress {path:?}: {e}"));
+					(Response::MimeBody(c, m, v), identity_etag)
+				}
+			},
+			(r, _) => (r, etag),
+		};
+		let response = match precompressed {
+			Some(encoding) => {
+				response.with_header(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()))
+			}
+			None => response,
+		};
+		let response = vary(response);
+
+		// ranges of route files are supported unless they're templates (compression is skipped for them)
+		let response = match response.status().is_success() && !template {
+			true => response.with_header(ACCEPT_RANGES, HeaderValue::from_static("bytes")),
+			false => response,
+		};
+		let response = match last_modified {
+			Some(date) => response.with_header(LAST_MODIFIED, date),
+			None => response,
+		};
+		Ok(match etag {
+			Some(etag) => response.with_header(ETAG, etag),
+			None => response,
+		})
+	}
+
+	/// Open the precompressed variant of the file at `path` (e.g. `style.css.br` for `style.css`)
+	/// for the most preferred of `encodings` that the request accepts, if there is one.
+	async fn open_precompressed(
+		path: &Path,
+		encodings: &[Encoding],
+		headers: &HeaderMap,
+	) -> Option<(Encoding, Content)> {
+		for &encoding in encodings {
+			if !accepts_encoding(headers, encoding.name()) {
+				continue;
+			}
+			let extension = match encoding {
+				Encoding::Brotli => "br",
+				Encoding::Gzip => "gz",
+			};
+			let variant = &variant_path(path, extension);
+			if !tokio::fs::metadata(variant)
+				.await
+				.is_ok_and(|m| m.is_file())
+			{
+				continue;
+			}
+			if let Ok(content) = Content::open(variant, None).await {
+				return Some((encoding, content));
+			}
+		}
+		None
+	}
+
+	/// The path of the variant of the file at `path` with the additional `extension`
+	fn variant_path(path: &Path, extension: &str) -> PathBuf {
+		let mut variant = path.as_os_str().to_owned();
+		variant.push(".");
+		variant.push(extension);
+		variant.into()
+	}
+
+	/// A route file that was read into memory at startup
+	#[derive(Debug, Clone)]
+	struct PreloadedFile {
+		data: Bytes,
+		modified: Option<SystemTime>,
+	}
+
+	/// Where the content of a route file comes from
+	enum Source {
+		Preloaded(Bytes),
+		File(File),
+	}
+
+	/// The opened content of a route file
+	struct Content {
+		source: Source,
+		len: u64,
+		modified: Option<SystemTime>,
+	}
+
+	impl Content {
+		/// Open the file at `path`, or use its `preloaded` content if available.
+		async fn open(path: &Path, preloaded: Option<&PreloadedFile>) -> std::io::Result<Self> {
+			Ok(match preloaded {
+				Some(p) => Self {
+					source: Source::Preloaded(p.data.clone()),
+					len: p.data.len() as u64,
+					modified: p.modified,
+				},
+				None => {
+					let file = File::open(path).await?;
+					let metadata = file.metadata().await?;
+					Self {
+						source: Source::File(file),
+						len: metadata.len(),
+						modified: metadata.modified().ok(),
+					}
+				}
+			})
+		}
+
+		/// The decompressed content of the gzipped variant of the file at `path` (e.g. `data.json.gz` for `data.json`)
+		async fn decompressed(path: &Path) -> std::io::Result<Self> {
+			use std::io::Read;
+
+			let variant = variant_path(path, "gz");
+			let compressed = tokio::fs::read(&variant).await?;
+			let modified = tokio::fs::metadata(&variant).await?.modified().ok();
+			let mut data = vec![];
+			GzDecoder::new(&compressed[..]).read_to_end(&mut data)?;
+			Ok(Self {
+				len: data.len() as u64,
+				source: Source::Preloaded(data.into()),
+				modified,
+			})
+		}
+
+		/// The embedded file at `path`
+		fn embedded(path: &Path) -> std::io::Result<Self> {
+			let data = embedded::get(path).ok_or(std::io::ErrorKind::NotFound)?;
+			Ok(Self {
+				source: Source::Preloaded(Bytes::from_static(data)),
+				len: data.len() as u64,
+				modified: None,
+			})
+		}
+
+		/// An ETag derived from the length and modification time
+		/// and the `encoding` of the representation (if it is compressed).
+		///
+		/// It is `weak` if the representation isn't sent byte for byte as it is stored.
+		fn etag(&self, encoding: Option<Encoding>, weak: bool) -> Option<HeaderValue> {
+			let modified = self.modified?.duration_since(UNIX_EPOCH).ok()?;
+			let suffix = encoding.map_or(String::new(), |e| format!("-{}", e.name()));
+			let prefix = if weak { "W/" } else { "" };
+			Some(header_value(format!(
+				"{prefix}\"{:x}-{:x}{suffix}\"",
+				self.len,
+				modified.as_nanos()
+			)))
+		}
+
+		/// Whether the whole content is streamed by `into_response` with `stream_threshold`
+		fn is_streamed(&self, stream_threshold: Option<u64>) -> bool {
+			matches!(self.source, Source::File(_)) && stream_threshold.is_some_and(|t| self.len > t)
+		}
+
+		/// Read the (optionally) requested ranges of the content.
+		///
+		/// If `head` is set, nothing is actually read.
+		/// If the response body would be larger than `stream_threshold`, it is streamed instead.
+		async fn into_response(
+			self,
+			mime: Option<SetMime>,
+			ranges: Option<Vec<ByteRange>>,
+			head: bool,
+			stream_threshold: Option<u64>,
+		) -> std::io::Result<Response> {
+			let len = self.len;
+			let mut bounds = match ranges {
+				None => vec![],
+				Some(ranges) => match satisfiable_bounds(&ranges, len) {
+					bounds if bounds.is_empty() => {
+						return Ok(Response::PureCode(StatusCode::RANGE_NOT_SATISFIABLE)
+							.with_header(CONTENT_RANGE, header_value(format!("bytes */{len}"))));
+					}
+					bounds => bounds,
+				},
+			};
+			if bounds.len() > 1 {
+				let parts_len: u64 = bounds.iter().map(|(start, end)| end - start + 1).sum();
+				if stream_threshold.is_some_and(|t| parts_len > t) {
+					// multipart responses are built in memory, so the whole file is streamed instead
+					bounds.clear();
+				} else {
+					return self.into_multipart_response(mime, &bounds, head).await;
+				}
+			}
+			let (status, bounds) = match bounds.first() {
+				None => (StatusCode::OK, None),
+				Some(&bounds) => (StatusCode::PARTIAL_CONTENT, Some(bounds)),
+			};
+			let (start, body_len) =
+				bounds.map_or((0, len), |(start, end)| (start, end - start + 1));
+
+			let response = match self.source {
+				_ if head => Response::MimeHead(status, mime, body_len),
+				Source::Preloaded(data) => {
+					let start = start as usize;
+					Response::MimeBody(status, mime, data.slice(start..start + body_len as usize))
+				}
+				Source::File(mut file) => {
+					if start > 0 {
+						file.seek(SeekFrom::Start(start)).await?;
+					}
+					if stream_threshold.is_some_and(|t| body_len > t) {
+						Response::MimeStream(status, mime, body_len, file.take(body_len))
+					} else {
+						let mut v = vec![0; body_len as usize];
+						file.read_exact(&mut v).await?;
+						Response::MimeBody(status, mime, v.into())
+					}
+				}
+			};
+
+			Ok(match bounds {
+				None => response,
+				Some((start, end)) => response.with_header(
+					CONTENT_RANGE,
+					header_value(format!("bytes {start}-{end}/{len}")),
+				),
+			})
+		}
+
+		/// Read the ranges with the (inclusive) `bounds` into a `multipart/byteranges` response.
+		///
+		/// If `head` is set, nothing is actually read.
+		async fn into_multipart_response(
+			mut self,
+			mime: Option<SetMime>,
+			bounds: &[(u64, u64)],
+			head: bool,
+		) -> std::io::Result<Response> {
+			let boundary = format!("{:016x}", RandomState::new().build_hasher().finish());
+			let content_type = match &mime {
+				Some(SetMime(mime)) => format!("Content-Type: {mime}\r\n"),
+				None => String::new(),
+			};
+			let mut body = vec![];
+			let mut skipped = 0;
+			for &(start, end) in bounds {
+				body.extend_from_slice(
+					format!(
+						"\r\n--{boundary}\r\n{content_type}Content-Range: bytes {start}-{end}/{}\r\n\r\n",
+						self.len
+					)
+					.as_bytes(),
+				);
+				if head {
+					skipped += end - start + 1;
+				} else {
+					body.extend_from_slice(&self.read_range(start, end).await?);
+				}
+			}
+			body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+			let mime = Mime::from_str(&format!("multipart/byteranges; boundary={boundary}"))
+				.ok()
+				.map(SetMime);
+			Ok(if head {
+				Response::MimeHead(
+					StatusCode::PARTIAL_CONTENT,
+					mime,
+					body.len() as u64 + skipped,
+				)
+			} else {
+				Response::MimeBody(StatusCode::PARTIAL_CONTENT, mime, body.into())
+			})
+		}
+
+		/// Read the bytes from `start` to `end` (inclusive)
+		async fn read_range(&mut self, start: u64, end: u64) -> std::io::Result<Bytes> {
+			let (start, len) = (start as usize, (end - start + 1) as usize);
+			match &mut self.source {
+				Source::Preloaded(data) => Ok(data.slice(start..start + len)),
+				Source::File(file) => {
+					file.seek(SeekFrom::Start(start as u64)).await?;
+					let mut v = vec![0; len];
+					file.read_exact(&mut v).await?;
+					Ok(v.into())
+				}
+			}
+		}
+	}
+
+	/// Format `time` as an HTTP-date (e.g. `Sun, 06 Nov 1994 08:49:37 GMT`)
+	fn http_date(time: SystemTime) -> HeaderValue {
+		header_value(httpdate::fmt_http_date(time))
+	}
+
+	/// Whether a file last modified at `modified` was modified since the `If-Modified-Since` header,
+	/// or `None` if there is no (valid) such header
+	fn modified_since(headers: &HeaderMap, modified: SystemTime) -> Option<bool> {
+		let since = headers.get(IF_MODIFIED_SINCE)?.to_str().ok()?;
+		let since = httpdate::parse_http_date(since).ok()?;
+		// HTTP-dates only have a resolution of seconds
+		let modified = modified.duration_since(UNIX_EPOCH).ok()?.as_secs();
+		let since = since.duration_since(UNIX_EPOCH).ok()?.as_secs();
+		Some(modified > since)
+	}
+
+	/// Whether the `If-None-Match` header matches `etag` (using weak comparison)
+	fn etag_matches(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+		let strip_weak = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_string();
+		let Ok(etag) = etag.to_str().map(strip_weak) else {
+			return false;
+		};
+		headers
+			.get_all(IF_NONE_MATCH)
+			.iter()
+			.filter_map(|v| v.to_str().ok())
+			.flat_map(|v| v.split(','))
+			.map(str::trim)
+			.any(|tag| tag == "*" || strip_weak(tag) == etag)
+	}
+
+	/// Replace every `{{var}}` in `body` with the value of `var` in `vars`
+	/// (surrounding whitespace in the braces is ignored).
+	///
+	/// Unknown variables are left as they are, and so is `body` if it isn't valid UTF-8.
+	fn fill_template(body: &Bytes, vars: &HashMap<String, String>) -> Bytes {
+		let Ok(mut rest) = std::str::from_utf8(body) else {
+			return body.clone();
+		};
+		let mut out = String::with_capacity(rest.len());
+		while let Some(start) = rest.find("{{") {
+			let Some(len) = rest[start + 2..].find("}}") else {
+				break;
+			};
+			let end = start + 2 + len + 2;
+			out.push_str(&rest[..start]);
+			match vars.get(rest[start + 2..end - 2].trim()) {
+				Some(value) => out.push_str(value),
+				None => out.push_str(&rest[start..end]),
+			}
+			rest = &rest[end..];
+		}
+		out.push_str(rest);
+		out.into()
+	}
+
+	/// Whether the `If-Range` header (if any) matches `etag` or `modified`, i.e. whether the `Range` header applies.
+	///
+	/// As per RFC 9110, entity tags are compared strongly (so weak ones never match)
+	/// and dates have to match exactly.
+	fn if_range_matches(
+		headers: &HeaderMap,
+		etag: Option<&HeaderValue>,
+		modified: Option<SystemTime>,
+	) -> bool {
+		let Some(value) = headers.get(IF_RANGE) else {
+			return true;
+		};
+		let Ok(value) = value.to_str() else {
+			return false;
+		};
+		if value.starts_with("W/") {
+			return false;
+		}
+		if value.starts_with('"') {
+			return etag.is_some_and(|etag| {
+				!etag.as_bytes().starts_with(b"W/") && etag.as_bytes() == value.as_bytes()
+			});
+		}
+		let (Ok(date), Some(modified)) = (httpdate::parse_http_date(value), modified) else {
+			return false;
+		};
+		// HTTP-dates only have a resolution of seconds
+		let secs = |time: SystemTime| time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+		secs(date).is_some() && secs(date) == secs(modified)
+	}
+
+	/// The byte ranges requested by the value of a `Range` header.
+	///
+	/// If the header is invalid or has more than `MAX_RANGES` ranges, this is `None`,
+	/// in which case the header is ignored (as allowed by RFC 9110).
+	fn parse_ranges(header: &str) -> Option<Vec<ByteRange>> {
+		/// This protects against requests for a huge number of tiny ranges
+		const MAX_RANGES: usize = 64;
+
+		let specs = header.trim().strip_prefix("bytes=")?;
+		let ranges = specs
+			.split(',')
+			.map(ByteRange::parse)
+			.collect::<Option<Vec<_>>>()?;
+		(ranges.len() <= MAX_RANGES).then_some(ranges)
+	}
+
+	/// The (inclusive) bounds of the satisfiable `ranges` in a file of length `len`,
+	/// sorted and with overlapping or adjacent ranges merged
+	fn satisfiable_bounds(ranges: &[ByteRange], len: u64) -> Vec<(u64, u64)> {
+		let mut bounds: Vec<_> = ranges.iter().filter_map(|r| r.bounds(len)).collect();
+		bounds.sort_unstable();
+		let mut merged: Vec<(u64, u64)> = Vec::with_capacity(bounds.len());
+		for (start, end) in bounds {
+			match merged.last_mut() {
+				Some((_, last_end)) if start <= *last_end + 1 => *last_end = end.max(*last_end),
+				_ => merged.push((start, end)),
+			}
+		}
+		merged
+	}
+
+	/// A single byte range as requested by a `Range` header
+	#[derive(Debug, Clone, Copy)]
+	enum ByteRange {
+		/// `<start>-` or `<start>-<end>` (where `end` is inclusive)
+		FromTo(u64, Option<u64>),
+		/// `-<len>`, i.e. the last `len` bytes
+		Suffix(u64),
+	}
+
+	impl ByteRange {
+		/// Parse a single range of a `Range` header (e.g. `0-99`)
+		fn parse(spec: &str) -> Option<Self> {
+			let (start, end) = spec.trim().split_once('-')?;
+			if start.is_empty() {
+				return end.parse().ok().map(Self::Suffix);
+			}
+			let start = start.parse().ok()?;
+			let end = match end {
+				"" => None,
+				end => Some(end.parse().ok()?),
+			};
+			if end.is_some_and(|end| end < start) {
+				return None;
+			}
+			Some(Self::FromTo(start, end))
+		}
+
+		/// The (inclusive) bounds of this range in a file of length `len`,
+		/// or `None` if it is unsatisfiable
+		fn bounds(self, len: u64) -> Option<(u64, u64)> {
+			match self {
+				_ if len == 0 => None,
+				Self::FromTo(start, _) if start >= len => None,
+				Self::FromTo(start, end) => {
+					Some((start, end.map_or(len - 1, |end| end.min(len - 1))))
+				}
+				Self::Suffix(0) => None,
+				Self::Suffix(n) => Some((len.saturating_sub(n), len - 1)),
+			}
+		}
+	}
+
+	/// Create a header value from a string that is known to be valid
+	fn header_value(s: String) -> HeaderValue {
+		HeaderValue::try_from(s).expect("generated header values are valid")
+	}
+
+	/// Whether compressing a response of this type is worth it
+	fn is_compressible(mime: &Mime) -> bool {
+		mime.type_() == mime::TEXT
+			|| matches!(
+				mime.essence_str(),
+				"application/javascript" | "application/json" | "application/xml" | "image/svg+xml"
+			)
+	}
+
+	/// Whether the `Accept-Encoding` header lists `encoding` (or `*`) with a nonzero q-value.
+	///
+	/// An entry for `encoding` itself takes priority over `*`, so e.g. `*, gzip;q=0` doesn't accept gzip.
+	fn accepts_encoding(headers: &HeaderMap, encoding: &str) -> bool {
+		let items = headers
+			.get_all(ACCEPT_ENCODING)
+			.iter()
+			.filter_map(|v| v.to_str().ok())
+			.flat_map(|v| v.split(','));
+		let mut wildcard = false;
+		for item in items {
+			let mut params = item.split(';').map(str::trim);
+			let name = params.next().unwrap_or_default();
+			let q = params
+				.find_map(|p| p.strip_prefix("q="))
+				.and_then(|q| q.parse::<f32>().ok())
+				.unwrap_or(1.0);
+			if name.eq_ignore_ascii_case(encoding) {
+				return q > 0.0;
+			}
+			if name == "*" {
+				wildcard = q > 0.0;
+			}
+		}
+		wildcard
+	}
+
+	/// The first of the `alternatives` whose MIME type has the highest (nonzero) q-value in the `Accept` header,
+	/// or the first one if the header is missing.
+	///
+	/// Returns `None` if none of them is acceptable.
+	fn negotiate<'a>(headers: &HeaderMap, alternatives: &'a [Route]) -> Option<&'a Route> {
+		let ranges: Vec<_> = headers
+			.get_all(ACCEPT)
+			.iter()
+			.filter_map(|v| v.to_str().ok())
+			.flat_map(|v| v.split(','))
+			.filter_map(|item| {
+				let mut params = item.split(';').map(str::trim);
+				let range = params.next().filter(|r| !r.is_empty())?;
+				let q = params
+					.find_map(|p| p.strip_prefix("q="))
+					.and_then(|q| q.parse::<f32>().ok())
+					.unwrap_or(1.0);
+				Some((range, q))
+			})
+			.collect();
+		if ranges.is_empty() {
+			return alternatives.first();
+		}
+		let mut best: Option<(&Route, f32)> = None;
+		for alternative in alternatives {
+			let q = accept_quality(&ranges, alternative.mime.as_ref());
+			if q > 0.0 && best.map_or(true, |(_, best)| q > best) {
+				best = Some((alternative, q));
+			}
+		}
+		best.map(|(alternative, _)| alternative)
+	}
+
+	/// The q-value of `mime` according to the most specific of the media `ranges` that matches it
+	/// (0 if none does). A missing MIME type only matches `*/*`.
+	fn accept_quality(ranges: &[(&str, f32)], mime: Option<&Mime>) -> f32 {
+		let matches = |range: &str| {
+			let specificity = match range.split_once('/')? {
+				("*", "*") => 0,
+				(ty, subtype) => {
+					let mime = mime?;
+					if !ty.eq_ignore_ascii_case(mime.type_().as_str()) {
+						return None;
+					}
+					match subtype {
+						"*" => 1,
+						s if s.eq_ignore_ascii_case(mime.subtype().as_str()) => 2,
+						_ => return None,
+					}
+				}
+			};
+			Some(specificity)
+		};
+		(ranges.iter())
+			.filter_map(|&(range, q)| Some((matches(range)?, q)))
+			.max_by_key(|&(specificity, _)| specificity)
+			.map_or(0.0, |(_, q)| q)
+	}
+
+	/// Whether the `Accept` header allows HTML (or is missing),
+	/// which is the case e.g. when a browser navigates to a page
+	fn accepts_html(headers: &HeaderMap) -> bool {
+		let mut types = headers
+			.get_all(ACCEPT)
+			.iter()
+			.filter_map(|v| v.to_str().ok())
+			.flat_map(|v| v.split(','))
+			.peekable();
+		if types.peek().is_none() {
+			return true;
+		}
+		types.any(|item| {
+			let name = item.split(';').next().unwrap_or_default().trim();
+			["text/html", "text/*", "*/*"].contains(&name)
+		})
+	}
+
+	fn compress(data: &[u8], encoding: Encoding) -> std::io::Result<Vec<u8>> {
+		use std::io::Write;
+
+		match encoding {
+			Encoding::Brotli => {
+				// quality 5 (of 11) is a good tradeoff for compressing on the fly
+				let mut encoder = brotli::CompressorWriter::new(vec![], 4096, 5, 22);
+				encoder.write_all(data)?;
+				Ok(encoder.into_inner())
+			}
+			Encoding::Gzip => {
+				let mut encoder = GzEncoder::new(vec![], Compression::default());
+				encoder.write_all(data)?;
+				encoder.finish()
+			}
+		}
+	}
+
+	/// Like [`serve`], but requests that don't match any route are passed to `handler` first
+	pub async fn serve_with_fallback(config: Config, handler: FallbackHandler) {
+		serve_inner(config, Some(handler)).await;
+	}
+
+	pub async fn serve(config: Config) {
+		serve_inner(config, None).await;
+	}
+
+	async fn serve_inner(config: Config, fallback: Option<FallbackHandler>) {
+		#[cfg(not(feature = "http2"))]
+		if config.http2 == Some(true) {
+			log::warn("the config file enables HTTP/2, but this build doesn't support it");
+		}
+		#[cfg(not(feature = "tls"))]
+		if config.tls.is_some() {
+			log::error("the config file enables TLS, but this build doesn't support it");
+			return;
+		}
+		#[cfg(feature = "tls")]
+		let tls = match &config.tls {
+			None => None,
+			Some(tls) => match tls::load_tls(&config, tls).await {
+				Ok(tls) => Some(tls),
+				Err(e) => {
+					log::error(format_args!("failed to load TLS certificate or key: {e}"));
+					return;
+				}
+			},
+		};
+
+		let mut listeners = vec![];
+		for group in &config.addrs {
+			let bound = setup_listeners(
+				group.iter(),
+				&config.file_dir,
+				config.socket,
+				config.bind_all,
+			)
+			.await;
+			if bound.is_empty() && config.addrs.len() > 1 {
+				log::error(format_args!("failed to bind to any of {group:?}"));
+			}
+			listeners.extend(bound);
+		}
+		if listeners.is_empty() {
+			return;
+		}
+		if let Some(file) = &config.bound_addr_file {
+			let file = config.file_dir.join(file.as_std_path());
+			let addrs = listeners
+				.iter()
+				.map(|l| Ok(l.address()? + "\n"))
+				.collect::<std::io::Result<String>>();
+			if let Err(e) = addrs.and_then(|addrs| std::fs::write(&file, addrs)) {
+				log::error(format_args!(
+					"failed to write the bound addresses to {file:?} ({e})"
+				));
+			}
+		}
+
+		#[cfg(feature = "tls")]
+		let redirect_http_from = config
+			.tls
+			.as_ref()
+			.and_then(|t| t.redirect_http_from.clone());
+		#[cfg(feature = "tls")]
+		let (file_dir, socket) = (config.file_dir.clone(), config.socket);
+		let http = config.http;
+		let limit = http
+			.max_connections
+			.map(|max| Arc::new(ConnectionLimit::new(max)));
+
+		let shutdown = CancellationToken::new();
+		tokio::spawn(shutdown_on_signal(shutdown.clone()));
+
+		let remaining_requests = config
+			.max_requests
+			.map(|n| Arc::new(AtomicU64::new(n.get())));
+		let (args, from_file) = (config.args.clone(), config.from_file);
+		let file_events = (args.watch || args.dev).then(|| FileEvents {
+			changes: broadcast::channel(64).0,
+			shutdown: shutdown.clone(),
+		});
+		let shared = Arc::new(Shared {
+			file_events,
+			fallback,
+			metrics: Metrics::default(),
+		});
+		let state = Arc::new(ArcSwap::from_pointee(State::load(config, shared).await));
+		// keep the watcher alive while serving
+		let _file_watcher = match args.watch || args.dev {
+			true => watch_files(state.clone()),
+			false => None,
+		};
+		let _watcher = match from_file {
+			true => watch_config(args, state.clone()),
+			false => None,
+		};
+		let app = {
+			let shutdown = shutdown.clone();
+			move |request| {
+				// keep using the same state for the whole request, even if the config is reloaded
+				let state = state.load_full();
+				let (shutdown, remaining) = (shutdown.clone(), remaining_requests.clone());
+				async move {
+					let response = app(&state, request).await;
+					if remaining.is_some_and(|r| r.fetch_sub(1, Ordering::Relaxed) == 1) {
+						log::info("served `max_requests` requests, shutting down");
+						shutdown.cancel();
+					}
+					response
+				}
+			}
+		};
+
+		#[cfg(feature = "tls")]
+		if let Some(tls) = tls {
+			let mut tcp_listeners = vec![];
+			for listener in listeners {
+				match listener {
+					// `axum_server` always sets `TCP_NODELAY`
+					Listener::Tcp(tcp, _) => tcp_listeners.push(tcp),
+					#[cfg(unix)]
+					Listener::Unix(_, path) => {
+						log::error("TLS is not supported on unix sockets");
+						remove_socket(&path);
+					}
+				}
+			}
+			let Some(first) = tcp_listeners.first() else {
+				return;
+			};
+			log::info("serving over HTTPS");
+			if let Some(addr) = redirect_http_from {
+				match first.local_addr() {
+					Ok(https_addr) => {
+						tls::spawn_https_redirect(
+							&addr,
+							&file_dir,
+							https_addr.port(),
+							socket,
+							http,
+							limit.clone(),
+							shutdown.clone(),
+						)
+						.await
+					}
+					Err(e) => log::error(format_args!(
+						"failed to get HTTPS port, not redirecting from {addr:?} ({e})"
+					)),
+				}
+			}
+
+			let handle = axum_server::Handle::new();
+			tokio::spawn({
+				let handle = handle.clone();
+				async move {
+					shutdown.cancelled().await;
+					handle.graceful_shutdown(None);
+				}
+			});
+			let servers: Vec<_> = tcp_listeners
+				.into_iter()
+				.map(|listener| {
+					let (tls, handle, app) = (tls.clone(), handle.clone(), app.clone());
+					tokio::spawn(serve_tls(listener, tls, app, http, limit.clone(), handle))
+				})
+				.collect();
+			wait_for_servers(servers).await;
+			return;
+		}
+
+		log::info("serving over plain HTTP");
+		let servers = listeners
+			.into_iter()
+			.map(|listener| {
+				let serve = listener.serve(app.clone(), http, limit.clone(), shutdown.clone());
+				tokio::spawn(serve)
+			})
+			.collect();
+		wait_for_servers(servers).await;
+	}
+
+	/// Wait until all `servers` are finished
+	async fn wait_for_servers(servers: Vec<tokio::task::JoinHandle<std::io::Result<()>>>) {
+		for server in servers {
+			match server.await {
+				Ok(Ok(())) => {}
+				Ok(Err(e)) => log::error(format_args!("server failed: {e}")),
+				Err(e) => log::error(format_args!("server task failed: {e}")),
+			}
+		}
+	}
+
+	/// Wait for SIGINT or (on unix) SIGTERM, then trigger `shutdown`.
+	///
+	/// The servers then stop accepting new connections, but finish the existing ones.
+	async fn shutdown_on_signal(shutdown: CancellationToken) {
+		let ctrl_c = async {
+			if let Err(e) = tokio::signal::ctrl_c().await {
+				log::error(format_args!("failed to listen for SIGINT: {e}"));
+				std::future::pending().await
+			}
+		};
+		#[cfg(unix)]
+		let terminate = async {
+			use tokio::signal::unix::{signal, SignalKind};
+
+			match signal(SignalKind::terminate()) {
+				Ok(mut s) => {
+					s.recv().await;
+				}
+				Err(e) => {
+					log::error(format_args!("failed to listen for SIGTERM: {e}"));
+					std::future::pending().await
+				}
+			}
+		};
+		#[cfg(not(unix))]
+		let terminate = std::future::pending::<()>();
+
+		tokio::select! {
+			() = ctrl_c => log::info("received SIGINT, shutting down"),
+			() = terminate => log::info("received SIGTERM, shutting down"),
+			() = shutdown.cancelled() => return,
+		}
+		shutdown.cancel();
+	}
+
+	/// Read all route files into memory, up to a total of `preload_max_bytes`.
+	async fn preload(config: &Config) -> HashMap<PathBuf, PreloadedFile> {
+		let mut paths: Vec<_> = (config.routes.exact.values())
+			.chain(config.vhosts.iter().flat_map(|v| v.routes.exact.values()))
+			.flat_map(Route::files)
+			.filter(|r| r.source == ContentSource::Disk)
+			.map(|r| &r.path)
+			.collect();
+		// sorted so that it's deterministic which files are left out if the limit is reached
+		paths.sort();
+		paths.dedup();
+
+		let mut preloaded = HashMap::new();
+		let mut total = 0;
+		for path in paths {
+			let log_path = path.strip_prefix(&config.file_dir).unwrap_or(path);
+			let (len, modified) = match tokio::fs::metadata(path).await {
+				Ok(m) => (m.len(), m.modified().ok()),
+				Err(e) => {
+					log::warn(format_args!("failed to preload {log_path:?} ({e})"));
+					continue;
+				}
+			};
+			if config
+				.preload_max_bytes
+				.is_some_and(|max| total + len > max)
+			{
+				log::warn(format_args!(
+					"not preloading {log_path:?} (would exceed `preload_max_bytes`)"
+				));
+				continue;
+			}
+			match tokio::fs::read(path).await {
+				Ok(data) => {
+					total += data.len() as u64;
+					let data = data.into();
+					preloaded.insert(path.clone(), PreloadedFile { data, modified });
+				}
+				Err(e) => log::warn(format_args!("failed to preload {log_path:?} ({e})")),
+			}
+		}
+
+		log::info(format_args!(
+			"preloaded {} files ({total} bytes)",
+			preloaded.len()
+		));
+		preloaded
+	}
+
+	/// A bound listener, either for TCP or for a unix domain socket
+	enum Listener {
+		/// A TCP listener and whether to set `TCP_NODELAY` for its connections
+		Tcp(TcpListener, Option<bool>),
+		#[cfg(unix)]
+		Unix(UnixListener, PathBuf),
+	}
+
+	impl Listener {
+		/// The address this is bound to, in the format of `addr`
+		fn address(&self) -> std::io::Result<String> {
+			Ok(match self {
+				Listener::Tcp(tcp, _) => tcp.local_addr()?.to_string(),
+				#[cfg(unix)]
+				Listener::Unix(_, path) => format!("{UNIX_PREFIX}{}", path.display()),
+			})
+		}
+
+		/// Serve `app` until `shutdown` is triggered
+		async fn serve<H, T>(
+			self,
+			app: H,
+			http: HttpConfig,
+			limit: Option<Arc<ConnectionLimit>>,
+			shutdown: CancellationToken,
+		) -> std::io::Result<()>
+		where
+			H: Handler<T, ()>,
+			T: 'static,
+		{
+			match self {
+				Listener::Tcp(tcp, nodelay) => {
+					let tcp = tcp.tap_io(move |stream| {
+						if let Some(nodelay) = nodelay {
+							if let Err(e) = stream.set_nodelay(nodelay) {
+								log::warn(format_args!("failed to set TCP_NODELAY ({e})"));
+							}
+						}
+					});
+					serve_connections(tcp, Some, app, http, limit, shutdown).await;
+				}
+				#[cfg(unix)]
+				Listener::Unix(unix, path) => {
+					serve_connections(unix, |_| None, app, http, limit, shutdown).await;
+					remove_socket(&path);
+				}
+			}
+			Ok(())
+		}
+	}
+
+	/// Apply the `http` section to `builder`.
+	///
+	/// Without a keep-alive timeout, no timer is set, which disables hyper's default header read timeout
+	/// (like `axum::serve`).
+	fn configure_http(builder: &mut auto::Builder<TokioExecutor>, http: HttpConfig) {
+		let mut http1 = builder.http1();
+		if let Some(keep_alive) = http.keep_alive {
+			http1.keep_alive(keep_alive);
+		}
+		if let Some(secs) = http.keep_alive_timeout_secs {
+			// the header read timeout starts as soon as the connection waits for the next request
+			http1
+				.timer(TokioTimer::new())
+				.header_read_timeout(Duration::from_secs(secs));
+		}
+	}
+
+	/// Accept connections from `listener` and serve `app` on them until `shutdown` is triggered,
+	/// then wait for the open connections to finish.
+	///
+	/// `client_addr` gets the client address from the address of a connection (if it has one).
+	/// Beyond the `limit`, new connections aren't accepted until one is closed.
+	async fn serve_connections<L, H, T>(
+		mut listener: L,
+		client_addr: fn(L::Addr) -> Option<SocketAddr>,
+		app: H,
+		http: HttpConfig,
+		limit: Option<Arc<ConnectionLimit>>,
+		shutdown: CancellationToken,
+	) where
+		L: axum::serve::Listener,
+		H: Handler<T, ()>,
+		T: 'static,
+	{
+		let builder = auto::Builder::new(TokioExecutor::new());
+		#[cfg(not(feature = "http2"))]
+		let builder = builder.http1_only();
+		let mut builder = builder;
+		configure_http(&mut builder, http);
+		let graceful = GracefulShutdown::new();
+		loop {
+			let permit = match &limit {
+				None => None,
+				Some(limit) => tokio::select! {
+					permit = limit.acquire() => Some(permit),
+					() = shutdown.cancelled() => break,
+				},
+			};
+			let (io, addr) = tokio::select! {
+				conn = listener.accept() => conn,
+				() = shutdown.cancelled() => break,
+			};
+			let service = ConnectionService::new(app.clone(), client_addr(addr), http, permit);
+			let conn = builder
+				.serve_connection(TokioIo::new(io), TowerToHyperService::new(service))
+				.into_owned();
+			let conn = graceful.watch(conn);
+			tokio::spawn(async move {
+				// errors are usually caused by clients (e.g. by closing the connection early)
+				let _ = conn.await;
+			});
+		}
+		drop(listener);
+		graceful.shutdown().await;
+	}
+
+	/// Serve `app` over TLS on `listener` until `handle` shuts it down
+	#[cfg(feature = "tls")]
+	async fn serve_tls<H, T>(
+		listener: TcpListener,
+		tls: axum_server::tls_rustls::RustlsConfig,
+		app: H,
+		http: HttpConfig,
+		limit: Option<Arc<ConnectionLimit>>,
+		handle: axum_server::Handle,
+	) -> std::io::Result<()>
+	where
+		H: Handler<T, ()>,
+		T: 'static,
+	{
+		let mut server = axum_server::from_tcp_rustls(listener.into_std()?, tls).handle(handle);
+		configure_http(server.http_builder(), http);
+		server
+			.serve(MakeConnectionService::new(app, http, limit))
+			.await
+	}
+
+	/// Creates a [`ConnectionService`] for each connection accepted by `axum_server`
+	#[cfg(feature = "tls")]
+	struct MakeConnectionService<H, T> {
+		app: H,
+		http: HttpConfig,
+		limit: Option<Arc<ConnectionLimit>>,
+		_handler: PhantomData<fn() -> T>,
+	}
+
+	#[cfg(feature = "tls")]
+	impl<H, T> MakeConnectionService<H, T> {
+		fn new(app: H, http: HttpConfig, limit: Option<Arc<ConnectionLimit>>) -> Self {
+			Self {
+				app,
+				http,
+				limit,
+				_handler: PhantomData,
+			}
+		}
+	}
+
+	#[cfg(feature = "tls")]
+	impl<H: Handler<T, ()>, T> tower_service::Service<SocketAddr> for MakeConnectionService<H, T> {
+		type Response = ConnectionService<H, T>;
+		type Error = Infallible;
+		type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+		fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+			Poll::Ready(Ok(()))
+		}
+
+		/// `axum_server` waits for this before accepting the next connection,
+		/// so waiting for a place within the `limit` here holds back further connections as well
+		fn call(&mut self, addr: SocketAddr) -> Self::Future {
+			let (app, http, limit) = (self.app.clone(), self.http, self.limit.clone());
+			Box::pin(async move {
+				let permit = match limit {
+					None => None,
+					Some(limit) => Some(limit.acquire().await),
+				};
+				Ok(ConnectionService::new(app, Some(addr), http, permit))
+			})
+		}
+	}
+
+	/// The limit of `max_connections` (shared by all listeners of a server)
+	struct ConnectionLimit {
+		permits: Arc<Semaphore>,
+		max: NonZeroUsize,
+		/// Whether the last connection was rejected, so that reaching the limit is only logged once
+		full: AtomicBool,
+	}
+
+	impl ConnectionLimit {
+		fn new(max: NonZeroUsize) -> Self {
+			Self {
+				permits: Arc::new(Semaphore::new(max.get())),
+				max,
+				full: AtomicBool::new(false),
+			}
+		}
+
+		/// Reserve a place for a new connection, waiting until one is free if the limit is reached
+		async fn acquire(&self) -> OwnedSemaphorePermit {
+			if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+				self.full.store(false, Ordering::Relaxed);
+				return permit;
+			}
+			if !self.full.swap(true, Ordering::Relaxed) {
+				log::warn(format_args!(
+					"reached `max_connections` ({}), new connections wait until one is closed",
+					self.max
+				));
+			}
+			(self.permits.clone().acquire_owned().await).expect("the semaphore is never closed")
+		}
+	}
+
+	/// Serves `app` on a single connection
+	struct ConnectionService<H, T> {
+		app: H,
+		client_addr: Option<SocketAddr>,
+		/// The number of requests that may still be sent before the connection is closed
+		/// (if `max_requests_per_connection` is set)
+		remaining_requests: Option<Arc<AtomicU64>>,
+		/// The place of this connection within `max_connections` (if it is set),
+		/// which is released when the connection (and so every clone of this) is dropped
+		permit: Option<Arc<OwnedSemaphorePermit>>,
+		_handler: PhantomData<fn() -> T>,
+	}
+
+	impl<H, T> ConnectionService<H, T> {
+		fn new(
+			app: H,
+			client_addr: Option<SocketAddr>,
+			http: HttpConfig,
+			permit: Option<OwnedSemaphorePermit>,
+		) -> Self {
+			Self {
+				app,
+				client_addr,
+				remaining_requests: http
+					.max_requests_per_connection
+					.map(|n| Arc::new(AtomicU64::new(n.get()))),
+				permit: permit.map(Arc::new),
+				_handler: PhantomData,
+			}
+		}
+	}
+
+	// not derived to avoid requiring `T: Clone`
+	impl<H: Clone, T> Clone for ConnectionService<H, T> {
+		fn clone(&self) -> Self {
+			Self {
+				app: self.app.clone(),
+				client_addr: self.client_addr,
+				remaining_requests: self.remaining_requests.clone(),
+				permit: self.permit.clone(),
+				_handler: PhantomData,
+			}
+		}
+	}
+
+	impl<H: Handler<T, ()>, T: 'static> tower_service::Service<Request<Incoming>>
+		for ConnectionService<H, T>
+	{
+		type Response = axum::response::Response;
+		type Error = Infallible;
+		type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+		fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn call(&mut self, mut request: Request<Incoming>) -> Self::Future {
+			if let Some(addr) = self.client_addr {
+				request.extensions_mut().insert(ConnectInfo(addr));
+			}
+			// HTTP/2 multiplexes requests, so only HTTP/1 connections are limited
+			let close = request.version() <= Version::HTTP_11
+				&& self
+					.remaining_requests
+					.as_ref()
+					.is_some_and(|r| r.fetch_sub(1, Ordering::Relaxed) == 1);
+			let response = self.app.clone().call(request.map(Body::new), ());
+			Box::pin(async move {
+				let mut response = response.await;
+				if close {
+					response
+						.headers_mut()
+						.insert(CONNECTION, HeaderValue::from_static("close"));
+				}
+				Ok(response)
+			})
+		}
+	}
+
+	/// Bind to the first of `addrs` that works, or to all of them if `bind_all` is set
+	async fn setup_listeners(
+		addrs: impl Iterator<Item = &String>,
+		root: &Path,
+		socket: SocketConfig,
+		bind_all: bool,
+	) -> Vec<Listener> {
+		let mut listeners = vec![];
+		for s in addrs {
+			if let Some(path) = s.strip_prefix(UNIX_PREFIX) {
+				#[cfg(unix)]
+				{
+					let path = root.join(path);
+					match with_retries(socket, s, || bind_unix(&path)).await {
+						Err(e) => log::warn(format_args!("failed to bind to socket {s:?} ({e})")),
+						Ok(unix) => {
+							log::info(format_args!("listening on {s:?}"));
+							listeners.push(Listener::Unix(unix, path));
+							if !bind_all {
+								return listeners;
+							}
+						}
+					}
+				}
+				#[cfg(not(unix))]
+				{
+					let _ = (path, root);
+					log::warn(format_args!(
+						"failed to bind to socket {s:?} (unix sockets are not supported on this platform)"
+					));
+				}
+				continue;
+			}
+			match s.to_socket_addrs() {
+				Err(e) => log::warn(format_args!("no socket addr found for {s:?} ({e})")),
+				Ok(addrs) => {
+					for addr in addrs {
+						match with_retries(socket, s, || bind_tcp(addr, socket)).await {
+							Err(e) => {
+								log::warn(format_args!(
+									"failed to bind to address {s:?} = {addr} ({e})"
+								));
+							}
+							Ok(tcp) => {
+								// this differs from `addr` if it has port 0
+								let bound = tcp.local_addr().unwrap_or(addr);
+								log::info(format_args!("listening on {s:?} = {bound}"));
+								listeners.push(Listener::Tcp(tcp, socket.nodelay));
+								if !bind_all {
+									return listeners;
+								}
+							}
+						}
+					}
+				}
+			}
+		}
+		listeners
+	}
+
+	/// Call `bind` (for the address `s`) until it succeeds,
+	/// retrying up to `socket.bind_retries` times with exponential backoff
+	async fn with_retries<T>(
+		socket: SocketConfig,
+		s: &str,
+		mut bind: impl FnMut() -> std::io::Result<T>,
+	) -> std::io::Result<T> {
+		let mut delay = Duration::from_millis(socket.bind_retry_delay_ms.unwrap_or(100));
+		for retry in 1..=socket.bind_retries {
+			match bind() {
+				Ok(x) => return Ok(x),
+				Err(e) => {
+					log::warn(format_args!(
+						"failed to bind to {s:?} ({e}), retrying in {delay:?} ({retry}/{})",
+						socket.bind_retries
+					));
+					tokio::time::sleep(delay).await;
+					delay = delay.saturating_mul(2);
+				}
+			}
+		}
+		bind()
+	}
+
+	fn bind_tcp(addr: SocketAddr, options: SocketConfig) -> std::io::Result<TcpListener> {
+		use socket2::{Domain, Protocol, Socket, Type};
+
+		let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+		// these defaults are the same as for `TcpListener::bind`
+		let reuse_addr = options.reuse_addr.unwrap_or(cfg!(unix));
+		let backlog = options.backlog.unwrap_or(1024);
+		socket.set_reuse_address(reuse_addr)?;
+		if let (SocketAddr::V6(_), Some(ipv6_only)) = (addr, options.ipv6_only) {
+			socket.set_only_v6(ipv6_only)?;
+		}
+		socket.set_nonblocking(true)?;
+		socket.bind(&addr.into())?;
+		socket.listen(backlog.try_into().unwrap_or(i32::MAX))?;
+		let tcp = TcpListener::from_std(socket.into())?;
+
+		let optional = |o: Option<bool>| o.map_or("default".to_string(), |o| o.to_string());
+		log::info(format_args!(
+			"socket options: backlog {backlog}, reuse_addr {reuse_addr}, nodelay {}, ipv6_only {}",
+			optional(options.nodelay),
+			optional(options.ipv6_only),
+		));
+		Ok(tcp)
+	}
+
+	#[cfg(unix)]
+	fn bind_unix(path: &Path) -> std::io::Result<UnixListener> {
+		use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+		// remove a socket left over from a previous run, but not one that is still in use
+		if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+			if std::os::unix::net::UnixStream::connect(path).is_ok() {
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::AddrInUse,
+					"socket is in use",
+				));
+			}
+			std::fs::remove_file(path)?;
+		}
+		let unix = UnixListener::bind(path)?;
+		// owner and group (e.g. a reverse proxy) may connect
+		std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+		Ok(unix)
+	}
+
+	#[cfg(unix)]
+	fn remove_socket(path: &Path) {
+		if let Err(e) = std::fs::remove_file(path) {
+			log::warn(format_args!("failed to remove socket {path:?} ({e})"));
+		}
+	}
+
+	async fn load_error_pages(
+		routes: &HashMap<StatusCode, Route>,
+	) -> HashMap<StatusCode, ErrorPage> {
+		let mut pages = HashMap::new();
+		for (status, route) in routes {
+			let status_code = status.as_u16();
+			let data = match route.source {
+				ContentSource::Disk => tokio::fs::read(&route.path).await.map(Bytes::from),
+				ContentSource::Embedded => embedded::get(&route.path)
+					.map(Bytes::from_static)
+					.ok_or(std::io::ErrorKind::NotFound.into()),
+			};
+			match data {
+				Ok(data) => {
+					log::info(format_args!("loaded {status_code} file"));
+					let page = ErrorPage {
+						mime: route.mime.clone().map(SetMime),
+						data,
+					};
+					pages.insert(*status, page);
+				}
+				Err(e) => log::error(format_args!("failed to load {status_code} file: {e}")),
+			}
+		}
+		pages
+	}
+
+	#[cfg(feature = "tls")]
+	mod tls {
+		use std::str::FromStr;
+
+		use std::net::SocketAddr;
+		use std::path::Path;
+		use std::sync::Arc;
+		use std::time::Instant;
+
+		use axum::body::Body;
+		use axum::extract::ConnectInfo;
+		use axum::http::header::{HOST, LOCATION};
+		use axum::http::uri::Authority;
+		use axum::http::{HeaderValue, Request, StatusCode};
+		use axum_server::tls_rustls::RustlsConfig;
+		use tokio_util::sync::CancellationToken;
+
+		use super::{path_and_query, setup_listeners, ConnectionLimit, Response};
+		use crate::config::{Config, HttpConfig, SocketConfig, TlsConfig};
+		use crate::log::{self, LogLevel, RequestLog};
+
+		/// Listen on `addr` and redirect all requests to HTTPS on the port `https_port`
+		pub async fn spawn_https_redirect(
+			addr: &String,
+			root: &Path,
+			https_port: u16,
+			socket: SocketConfig,
+			http: HttpConfig,
+			limit: Option<Arc<ConnectionLimit>>,
+			shutdown: CancellationToken,
+		) {
+			let listener = setup_listeners(std::iter::once(addr), root, socket, false).await;
+			let Some(listener) = listener.into_iter().next() else {
+				log::error(format_args!("not redirecting from {addr:?} to HTTPS"));
+				return;
+			};
+			let app = move |request| async move { redirect_to_https(https_port, request) };
+			tokio::spawn(async move {
+				if let Err(e) = listener.serve(app, http, limit, shutdown).await {
+					log::error(format_args!("HTTPS redirect server failed: {e}"));
+				}
+			});
+		}
+
+		fn redirect_to_https(https_port: u16, request: Request<Body>) -> Response {
+			let start = Instant::now();
+			let uri = request.uri();
+			let client = request
+				.extensions()
+				.get::<ConnectInfo<SocketAddr>>()
+				.map(|ConnectInfo(addr)| addr.ip());
+			let host = request
+				.headers()
+				.get(HOST)
+				.and_then(|h| h.to_str().ok())
+				.and_then(|h| Authority::from_str(h).ok())
+				.or_else(|| uri.authority().cloned());
+			let mut log = RequestLog::new(&request, client, "http");
+			let Some(host) = host else {
+				log.status = 400;
+				log.duration = start.elapsed();
+				log.note = "not redirected to HTTPS (missing host)".into();
+				log::request(LogLevel::Info, &log);
+				return Response::PureCode(StatusCode::BAD_REQUEST);
+			};
+			let port = match https_port {
+				443 => String::new(),
+				port => format!(":{port}"),
+			};
+			let location = format!("https://{}{port}{}", host.host(), path_and_query(uri));
+			log.status = 301;
+			log.duration = start.elapsed();
+			log.note = format!("redirect to {location}");
+			log::request(LogLevel::Info, &log);
+			match HeaderValue::try_from(location) {
+				Ok(location) => Response::PureCode(StatusCode::MOVED_PERMANENTLY)
+					.with_header(LOCATION, location),
+				Err(_) => Response::PureCode(StatusCode::BAD_REQUEST),
+			}
+		}
+
+		pub async fn load_tls(config: &Config, tls: &TlsConfig) -> std::io::Result<RustlsConfig> {
+			// this fails if a provider is already installed, which is fine
+			let _ = rustls::crypto::ring::default_provider().install_default();
+
+			let rustls_config = RustlsConfig::from_pem_file(
+				config.file_dir.join(tls.cert.as_std_path()),
+				config.file_dir.join(tls.key.as_std_path()),
+			)
+			.await?;
+			if cfg!(feature = "http2") && config.http2 != Some(false) {
+				return Ok(rustls_config);
+			}
+			// only offer HTTP/1.1 during ALPN
+			let mut server_config = (*rustls_config.get_inner()).clone();
+			server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+			Ok(RustlsConfig::from_config(Arc::new(server_config)))
+		}
+	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::*;
+		use tower::ServiceExt;
+
+		use crate::testing::{load_config, try_load_config, TestDir};
+
+		/// The state for serving the config file `config` (without `addr`) in a directory with `files`
+		async fn load(config: &str, files: &[(&str, &str)]) -> (TestDir, Arc<State>) {
+			let (dir, config) = load_config(config, files);
+			(dir, Arc::new(State::load(config, Arc::default()).await))
+		}
+
+		/// Send `request` to the handler that `serve` serves `state` with
+		async fn send(state: &Arc<State>, request: Request<Body>) -> axum::response::Response {
+			let state = state.clone();
+			let handler = move |request: Request<Body>| async move { app(&state, request).await };
+			handler.with_state(()).oneshot(request).await.unwrap()
+		}
+
+		/// A request with `method` for `uri` with `headers`
+		fn request(method: Method, uri: &str, headers: &[(&str, &str)]) -> Request<Body> {
+			let mut request = Request::builder().method(method).uri(uri);
+			for (name, value) in headers {
+				request = request.header(*name, *value);
+			}
+			request.body(Body::empty()).unwrap()
+		}
+
+		/// Send a GET request for `uri` with `headers`
+		async fn get(
+			state: &Arc<State>,
+			uri: &str,
+			headers: &[(&str, &str)],
+		) -> axum::response::Response {
+			send(state, request(Method::GET, uri, headers)).await
+		}
+
+		async fn body(response: axum::response::Response) -> Bytes {
+			axum::body::to_bytes(response.into_body(), usize::MAX)
+				.await
+				.unwrap()
+		}
+
+		/// The value of the header `name` of `response` as a string
+		fn header<'a>(response: &'a axum::response::Response, name: &str) -> Option<&'a str> {
+			response.headers().get(name).map(|v| v.to_str().unwrap())
+		}
+
+		#[tokio::test]
+		async fn head_mirrors_get_without_body() {
+			let (_dir, state) = load(
+				"[get_routes]\n\"a\" = \"a.html\"\n",
+				&[("a.html", "<p>a</p>")],
+			)
+			.await;
+			let get_response = get(&state, "/a", &[]).await;
+			let head = send(&state, request(Method::HEAD, "/a", &[])).await;
+			assert_eq!(head.status(), StatusCode::OK);
+			for name in ["content-type", "content-length", "etag", "last-modified"] {
+				assert_eq!(header(&head, name), header(&get_response, name), "{name}");
+			}
+			assert_eq!(header(&head, "content-length"), Some("8"));
+			assert!(body(head).await.is_empty());
+			assert_eq!(body(get_response).await, "<p>a</p>");
+		}
+
+		#[tokio::test]
+		async fn head_error_pages_have_no_body() {
+			let (_dir, state) = load("404 = \"404.html\"\n", &[("404.html", "not here")]).await;
+			let head = send(&state, request(Method::HEAD, "/missing", &[])).await;
+			assert_eq!(head.status(), StatusCode::NOT_FOUND);
+			assert_eq!(header(&head, "content-length"), Some("8"));
+			assert!(body(head).await.is_empty());
+		}
+
+		#[tokio::test]
+		async fn cache_control_only_on_configured_routes() {
+			let config = r#"
+				[get_routes]
+				"cached" = { path = "a.txt", cache_control = "max-age=3600" }
+				"plain" = "a.txt"
+			"#;
+			let (_dir, state) = load(config, &[("a.txt", "a")]).await;
+			let cached = get(&state, "/cached", &[]).await;
+			assert_eq!(cached.status(), StatusCode::OK);
+			assert_eq!(header(&cached, "cache-control"), Some("max-age=3600"));
+			let plain = get(&state, "/plain", &[]).await;
+			assert_eq!(plain.status(), StatusCode::OK);
+			assert_eq!(header(&plain, "cache-control"), None);
+		}
+
+		#[tokio::test]
+		async fn gzip_only_for_accepting_clients_and_text() {
+			use std::io::Read;
+
+			let config = r#"
+				compression = true
+				compression_algorithms = ["gzip"]
+				[get_routes]
+				"a.css" = "a.css"
+				"a.png" = "a.png"
+			"#;
+			let css = "body { color: red; }".repeat(20);
+			let (_dir, state) =
+				load(config, &[("a.css", &css), ("a.png", "not really a png")]).await;
+
+			let gzipped = get(&state, "/a.css", &[("accept-encoding", "gzip")]).await;
+			assert_eq!(header(&gzipped, "content-encoding"), Some("gzip"));
+			let mut decompressed = String::new();
+			GzDecoder::new(&body(gzipped).await[..])
+				.read_to_string(&mut decompressed)
+				.unwrap();
+			assert_eq!(decompressed, css);
+
+			let raw = get(&state, "/a.css", &[]).await;
+			assert_eq!(header(&raw, "content-encoding"), None);
+			assert_eq!(body(raw).await, css);
+
+			let binary = get(&state, "/a.png", &[("accept-encoding", "gzip")]).await;
+			assert_eq!(header(&binary, "content-encoding"), None);
+			assert_eq!(body(binary).await, "not really a png");
+		}
+
+		#[tokio::test]
+		async fn preloaded_files_survive_deletion() {
+			let config = r#"
+				preload = true
+				preload_max_bytes = 10
+				[get_routes]
+				"small" = "small.txt"
+				"large" = "large.txt"
+			"#;
+			let files = [
+				("small.txt", "small"),
+				("large.txt", "larger than the limit"),
+			];
+			let (dir, state) = load(config, &files).await;
+			std::fs::remove_file(dir.0.join("small.txt")).unwrap();
+			std::fs::remove_file(dir.0.join("large.txt")).unwrap();
+
+			let small = get(&state, "/small", &[]).await;
+			assert_eq!(small.status(), StatusCode::OK);
+			assert_eq!(body(small).await, "small");
+			// this exceeded `preload_max_bytes`, so it is read from disk
+			let large = get(&state, "/large", &[]).await;
+			assert_eq!(large.status(), StatusCode::NOT_FOUND);
+		}
+
+		#[tokio::test]
+		async fn if_none_match() {
+			let config = "[get_routes]\n\"a\" = \"a.txt\"\n";
+			let (_dir, state) = load(config, &[("a.txt", "a")]).await;
+			let response = get(&state, "/a", &[]).await;
+			let etag = header(&response, "etag").unwrap().to_string();
+
+			let matching = get(&state, "/a", &[("if-none-match", &etag)]).await;
+			assert_eq!(matching.status(), StatusCode::NOT_MODIFIED);
+			assert_eq!(header(&matching, "etag"), Some(&*etag));
+			assert!(body(matching).await.is_empty());
+
+			let other = get(&state, "/a", &[("if-none-match", "\"other\"")]).await;
+			assert_eq!(other.status(), StatusCode::OK);
+			assert_eq!(body(other).await, "a");
+		}
+
+		#[test]
+		fn http_dates() {
+			let time = UNIX_EPOCH + Duration::from_secs(784111777);
+			assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+
+			let headers =
+				|since: &str| HeaderMap::from_iter([(IF_MODIFIED_SINCE, since.parse().unwrap())]);
+			let same = headers("Sun, 06 Nov 1994 08:49:37 GMT");
+			// the fraction of a second is ignored
+			assert_eq!(
+				modified_since(&same, time + Duration::from_millis(500)),
+				Some(false)
+			);
+			let earlier = headers("Sun, 06 Nov 1994 08:49:36 GMT");
+			assert_eq!(modified_since(&earlier, time), Some(true));
+			assert_eq!(modified_since(&headers("yesterday"), time), None);
+			assert_eq!(modified_since(&HeaderMap::new(), time), None);
+		}
+
+		#[tokio::test]
+		async fn if_modified_since() {
+			let config = "[get_routes]\n\"a\" = \"a.txt\"\n";
+			let (_dir, state) = load(config, &[("a.txt", "a")]).await;
+			let response = get(&state, "/a", &[]).await;
+			let modified = header(&response, "last-modified").unwrap().to_string();
+
+			let unchanged = get(&state, "/a", &[("if-modified-since", &modified)]).await;
+			assert_eq!(unchanged.status(), StatusCode::NOT_MODIFIED);
+			assert!(body(unchanged).await.is_empty());
+
+			let old = "Sun, 06 Nov 1994 08:49:37 GMT";
+			let changed = get(&state, "/a", &[("if-modified-since", old)]).await;
+			assert_eq!(changed.status(), StatusCode::OK);
+			assert_eq!(header(&changed, "last-modified"), Some(&*modified));
+			assert_eq!(body(changed).await, "a");
+		}
+
+		#[tokio::test]
+		async fn directory_routes() {
+			let files = [
+				("assets/index.html", "index"),
+				("assets/style.css", "css"),
+				("assets/sub/index.html", "sub"),
+				("secret", "secret"),
+			];
+			let config = "[get_routes.dirs]\n\"assets\" = \"assets\"\n";
+			let (_dir, state) = load(config, &files).await;
+			let response = get(&state, "/assets/style.css", &[]).await;
+			assert_eq!(
+				header(&response, "content-type"),
+				Some("text/css; charset=utf-8")
+			);
+			assert_eq!(body(response).await, "css");
+			assert_eq!(body(get(&state, "/assets/", &[]).await).await, "index");
+			assert_eq!(body(get(&state, "/assets/sub/", &[]).await).await, "sub");
+			for uri in [
+				"/assets/../secret",
+				"/assets/%2e%2e/secret",
+				"/assets/missing",
+			] {
+				assert_eq!(get(&state, uri, &[]).await.status(), StatusCode::NOT_FOUND);
+			}
+			// without a trailing slash, the relative links of the index would break
+			for uri in ["/assets", "/assets/sub"] {
+				assert_eq!(get(&state, uri, &[]).await.status(), StatusCode::NOT_FOUND);
+			}
+
+			let config = format!("trailing_slash = \"redirect\"\n{config}");
+			let (_dir, state) = load(&config, &files).await;
+			for uri in ["/assets", "/assets/sub"] {
+				let response = get(&state, &format!("{uri}?a=b"), &[]).await;
+				assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+				assert_eq!(header(&response, "location"), Some(&*format!("{uri}/?a=b")));
+			}
+		}
+
+		#[tokio::test]
+		async fn basic_auth() {
+			let config = r#"
+				[auth]
+				users = { alice = "secret" }
+				realm = "test"
+				[get_routes]
+				"private" = { path = "a.txt", auth_required = true }
+				"public" = "a.txt"
+			"#;
+			let (_dir, state) = load(config, &[("a.txt", "a")]).await;
+			let missing = get(&state, "/private", &[]).await;
+			assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+			assert_eq!(
+				header(&missing, "www-authenticate"),
+				Some(r#"Basic realm="test""#)
+			);
+			// "alice:wrong" and "bob:secret"
+			for credentials in ["YWxpY2U6d3Jvbmc=", "Ym9iOnNlY3JldA=="] {
+				let authorization = format!("Basic {credentials}");
+				let wrong = get(&state, "/private", &[("authorization", &authorization)]).await;
+				assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+			}
+			// "alice:secret"
+			let authorization = [("authorization", "Basic YWxpY2U6c2VjcmV0")];
+			let authorized = get(&state, "/private", &authorization).await;
+			assert_eq!(authorized.status(), StatusCode::OK);
+			assert_eq!(body(authorized).await, "a");
+			assert_eq!(get(&state, "/public", &[]).await.status(), StatusCode::OK);
+		}
+
+		#[tokio::test]
+		async fn auth_for_all_routes() {
+			let config = r#"
+				[auth]
+				users = { alice = "secret" }
+				all_routes = true
+				[get_routes]
+				"private" = "a.txt"
+				"public" = { path = "a.txt", auth_required = false }
+			"#;
+			let (_dir, state) = load(config, &[("a.txt", "a")]).await;
+			let private = get(&state, "/private", &[]).await;
+			assert_eq!(private.status(), StatusCode::UNAUTHORIZED);
+			assert_eq!(get(&state, "/public", &[]).await.status(), StatusCode::OK);
+		}
+
+		#[test]
+		fn constant_time_comparison() {
+			assert!(constant_time_eq(b"secret", b"secret"));
+			assert!(!constant_time_eq(b"secret", b"secreT"));
+			assert!(!constant_time_eq(b"secret", b"secret2"));
+			assert!(constant_time_eq(b"", b""));
+		}
+
+		#[tokio::test]
+		async fn cors() {
+			let config = r#"
+				[cors]
+				allowed_origins = ["https://example.com"]
+				allowed_headers = ["X-Custom"]
+				max_age = 600
+				[get_routes]
+				"a" = "a.txt"
+			"#;
+			let (_dir, state) = load(config, &[("a.txt", "a")]).await;
+			let allowed = get(&state, "/a", &[("origin", "https://example.com")]).await;
+			assert_eq!(allowed.status(), StatusCode::OK);
+			let origin = header(&allowed, "access-control-allow-origin");
+			assert_eq!(origin, Some("https://example.com"));
+			assert_eq!(header(&allowed, "vary"), Some("Origin"));
+			assert_eq!(header(&allowed, "access-control-allow-methods"), None);
+
+			let other = get(&state, "/a", &[("origin", "https://example.org")]).await;
+			assert_eq!(other.status(), StatusCode::OK);
+			assert_eq!(header(&other, "access-control-allow-origin"), None);
+
+			let headers = [
+				("origin", "https://example.com"),
+				("access-control-request-method", "GET"),
+			];
+			let preflight = send(&state, request(Method::OPTIONS, "/a", &headers)).await;
+			assert!(preflight.status().is_success());
+			let origin = header(&preflight, "access-control-allow-origin");
+			assert_eq!(origin, Some("https://example.com"));
+			let methods = header(&preflight, "access-control-allow-methods");
+			assert_eq!(methods, Some("GET, HEAD, OPTIONS"));
+			let allowed_headers = header(&preflight, "access-control-allow-headers");
+			assert_eq!(allowed_headers, Some("X-Custom"));
+			assert_eq!(header(&preflight, "access-control-max-age"), Some("600"));
+		}
+
+		#[tokio::test]
+		async fn cors_for_all_origins() {
+			let config = r#"
+				[cors]
+				allowed_origins = ["*"]
+				[get_routes]
+				"a" = "a.txt"
+			"#;
+			let (_dir, state) = load(config, &[("a.txt", "a")]).await;
+			let response = get(&state, "/a", &[("origin", "https://example.org")]).await;
+			assert_eq!(header(&response, "access-control-allow-origin"), Some("*"));
+			assert_eq!(header(&response, "vary"), None);
+			let same_origin = get(&state, "/a", &[]).await;
+			assert_eq!(header(&same_origin, "access-control-allow-origin"), None);
+		}
+
+		#[tokio::test]
+		async fn wildcard_routes() {
+			let config = r#"
+				[get_routes]
+				"static/*" = "assets/*"
+				"static/special.css" = "special.css"
+				"static/deep/*" = "deep.txt"
+				"*.css" = "style.css"
+			"#;
+			let files = [
+				("assets/a/b.png", "png"),
+				("assets/c.txt", "c"),
+				("special.css", "special"),
+				("deep.txt", "deep"),
+				("style.css", "style"),
+				("secret", "secret"),
+			];
+			let (_dir, state) = load(config, &files).await;
+			let png = get(&state, "/static/a/b.png", &[]).await;
+			assert_eq!(header(&png, "content-type"), Some("image/png"));
+			assert_eq!(body(png).await, "png");
+			// exact routes take priority, then the longest prefix
+			assert_eq!(
+				body(get(&state, "/static/special.css", &[]).await).await,
+				"special"
+			);
+			assert_eq!(
+				body(get(&state, "/static/deep/x.css", &[]).await).await,
+				"deep"
+			);
+			assert_eq!(body(get(&state, "/static/c.txt", &[]).await).await, "c");
+			assert_eq!(body(get(&state, "/x/y.css", &[]).await).await, "style");
+			// the longer prefix wins even if its file doesn't exist
+			let missing = get(&state, "/static/y.css", &[]).await;
+			assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+			for uri in ["/static/../secret", "/static/a/..%2f..%2fsecret", "/x.txt"] {
+				assert_eq!(get(&state, uri, &[]).await.status(), StatusCode::NOT_FOUND);
+			}
+		}
+
+		#[tokio::test]
+		async fn fallback() {
+			let config = r#"
+				404 = "404.html"
+				fallback = "index.html"
+				[get_routes]
+				"a" = "a.txt"
+			"#;
+			let files = [
+				("a.txt", "a"),
+				("index.html", "app"),
+				("404.html", "not found"),
+			];
+			let (_dir, state) = load(config, &files).await;
+			for headers in [&[][..], &[("accept", "text/html,*/*;q=0.8")]] {
+				let response = get(&state, "/some/client/route", headers).await;
+				assert_eq!(response.status(), StatusCode::OK);
+				assert_eq!(body(response).await, "app");
+			}
+			assert_eq!(body(get(&state, "/a", &[]).await).await, "a");
+
+			let json = get(&state, "/api/missing", &[("accept", "application/json")]).await;
+			assert_eq!(json.status(), StatusCode::NOT_FOUND);
+			assert_eq!(body(json).await, "not found");
+		}
+
+		#[tokio::test]
+		async fn charset_of_text_types() {
+			let config = r#"
+				[get_routes]
+				"index" = "index.html"
+				"explicit" = { path = "index.html", type = "text/html" }
+				"image" = "a.png"
+			"#;
+			let files = [("index.html", "<p>ä</p>"), ("a.png", "png")];
+			let (_dir, state) = load(config, &files).await;
+			let inferred = get(&state, "/index", &[]).await;
+			let content_type = header(&inferred, "content-type");
+			assert_eq!(content_type, Some("text/html; charset=utf-8"));
+			// explicit types are left as they are
+			let explicit = get(&state, "/explicit", &[]).await;
+			assert_eq!(header(&explicit, "content-type"), Some("text/html"));
+			let image = get(&state, "/image", &[]).await;
+			assert_eq!(header(&image, "content-type"), Some("image/png"));
+		}
+
+		#[tokio::test]
+		async fn rate_limit() {
+			let config = r#"
+				[rate_limit]
+				requests_per_second = 1
+				burst = 2
+				[get_routes]
+				"a" = "a.txt"
+			"#;
+			let (_dir, state) = load(config, &[("a.txt", "a")]).await;
+			let from = |ip: [u8; 4]| {
+				let mut request = request(Method::GET, "/a", &[]);
+				let addr = SocketAddr::from((ip, 1234));
+				request.extensions_mut().insert(ConnectInfo(addr));
+				request
+			};
+			for _ in 0..2 {
+				let response = send(&state, from([10, 0, 0, 1])).await;
+				assert_eq!(response.status(), StatusCode::OK);
+			}
+			let limited = send(&state, from([10, 0, 0, 1])).await;
+			assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+			assert_eq!(header(&limited, "retry-after"), Some("1"));
+			// every client has its own bucket
+			let other = send(&state, from([10, 0, 0, 2])).await;
+			assert_eq!(other.status(), StatusCode::OK);
+		}
+
+		#[tokio::test]
+		async fn forwarded_client_address() {
+			let config = "trust_proxy = true\n";
+			let (_dir, state) = load(config, &[]).await;
+			let forwarded = |values: &[&str]| {
+				let mut request = request(Method::GET, "/", &[]);
+				for value in values {
+					let value = HeaderValue::from_str(value).unwrap();
+					request.headers_mut().append("x-forwarded-for", value);
+				}
+				let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+				request.extensions_mut().insert(ConnectInfo(addr));
+				request
+			};
+			let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+			let request = forwarded(&["10.0.0.1, 10.0.0.2", "10.0.0.3"]);
+			assert_eq!(client_ip(&state.config, &request), ip("10.0.0.1"));
+			assert_eq!(client_ip(&state.config, &forwarded(&[])), ip("127.0.0.1"));
+
+			let config = "trust_proxy = true\ntrusted_proxy_hops = 2\n";
+			let (_dir, state) = load(config, &[]).await;
+			let request = forwarded(&["10.0.0.1, 10.0.0.2", "10.0.0.3"]);
+			assert_eq!(client_ip(&state.config, &request), ip("10.0.0.2"));
+			// all entries were added by trusted proxies
+			let request = forwarded(&["10.0.0.3"]);
+			assert_eq!(client_ip(&state.config, &request), ip("10.0.0.3"));
+
+			let (_dir, state) = load("", &[]).await;
+			let request = forwarded(&["10.0.0.1"]);
+			assert_eq!(client_ip(&state.config, &request), ip("127.0.0.1"));
+		}
+
+		#[tokio::test]
+		async fn forwarded_scheme_in_redirects() {
+			let config = r#"
+				trust_proxy = true
+				trailing_slash = "redirect"
+				[redirects]
+				"old" = { to = "/new" }
+				"away" = { to = "https://example.org/" }
+				[get_routes]
+				"dir/" = "a.txt"
+			"#;
+			let (_dir, state) = load(config, &[("a.txt", "a")]).await;
+			let headers = [("host", "example.com"), ("x-forwarded-proto", "https")];
+			let response = get(&state, "/dir?a", &headers).await;
+			assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+			assert_eq!(
+				header(&response, "location"),
+				Some("https://example.com/dir/?a")
+			);
+			let response = get(&state, "/old", &headers).await;
+			assert_eq!(
+				header(&response, "location"),
+				Some("https://example.com/new")
+			);
+			let response = get(&state, "/away", &headers).await;
+			assert_eq!(header(&response, "location"), Some("https://example.org/"));
+			let response = get(&state, "/old", &[("host", "example.com")]).await;
+			assert_eq!(
+				header(&response, "location"),
+				Some("http://example.com/new")
+			);
+			// without a host, the location stays relative
+			let response = get(&state, "/old", &[]).await;
+			assert_eq!(header(&response, "location"), Some("/new"));
+
+			let config = "[redirects]\n\"old\" = { to = \"/new\" }\n";
+			let (_dir, state) = load(config, &[]).await;
+			let response = get(&state, "/old", &headers).await;
+			assert_eq!(header(&response, "location"), Some("/new"));
+		}
+
+		#[tokio::test]
+		async fn health_check() {
+			let config = r#"
+				health_path = "/healthz"
+				[auth]
+				users = { alice = "secret" }
+				all_routes = true
+				[rate_limit]
+				requests_per_second = 1
+				[get_routes]
+				"a" = "a.txt"
+			"#;
+			let (_dir, state) = load(config, &[("a.txt", "a")]).await;
+			for _ in 0..3 {
+				let mut request = request(Method::GET, "/healthz", &[]);
+				let addr = SocketAddr::from(([10, 0, 0, 1], 1234));
+				request.extensions_mut().insert(ConnectInfo(addr));
+				let response = send(&state, request).await;
+				assert_eq!(response.status(), StatusCode::OK);
+			}
+			assert_eq!(
+				get(&state, "/a", &[]).await.status(),
+				StatusCode::UNAUTHORIZED
+			);
+		}
+
+		#[tokio::test]
+		async fn health_check_without_routes() {
+			let (_dir, state) = load("health_path = \"healthz\"\n", &[]).await;
+			let response = get(&state, "/healthz", &[]).await;
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(get(&state, "/", &[]).await.status(), StatusCode::NOT_FOUND);
+		}
+
+		#[tokio::test]
+		async fn route_to_directory() {
+			let config = "[get_routes]\n\"d\" = \"dir\"\n\"d/\" = \"dir\"\n";
+			let (_dir, state) = load(config, &[("dir/a.txt", "a")]).await;
+			for uri in ["/d", "/d/"] {
+				let response = get(&state, uri, &[]).await;
+				assert_eq!(response.status(), StatusCode::NOT_FOUND);
+			}
+		}
+
+		#[test]
+		fn io_error_statuses() {
+			use std::io::{Error, ErrorKind};
+
+			let status = |kind: ErrorKind| io_error_response(&Error::from(kind)).status();
+			assert_eq!(status(ErrorKind::NotFound), StatusCode::NOT_FOUND);
+			assert_eq!(status(ErrorKind::PermissionDenied), StatusCode::FORBIDDEN);
+			assert_eq!(status(ErrorKind::Other), StatusCode::INTERNAL_SERVER_ERROR);
+		}
+
+		#[cfg(unix)]
+		#[tokio::test]
+		async fn unreadable_file() {
+			use std::os::unix::fs::PermissionsExt;
+
+			let config = "[get_routes]\n\"a\" = \"a.txt\"\n";
+			let (dir, state) = load(config, &[("a.txt", "a")]).await;
+			let path = dir.0.join("a.txt");
+			std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o000)).unwrap();
+			if std::fs::read(&path).is_ok() {
+				// e.g. when running as root
+				return;
+			}
+			let response = get(&state, "/a", &[]).await;
+			assert_eq!(response.status(), StatusCode::FORBIDDEN);
+		}
+
+		/// Serve `state` on `listener` until the returned token is cancelled
+		fn spawn_server(state: Arc<State>, listener: Listener) -> CancellationToken {
+			let shutdown = CancellationToken::new();
+			let http = state.config.http;
+			let handler = move |request: Request<Body>| {
+				let state = state.clone();
+				async move { app(&state, request).await }
+			};
+			let limit = http
+				.max_connections
+				.map(|max| Arc::new(ConnectionLimit::new(max)));
+			tokio::spawn(listener.serve(handler, http, limit, shutdown.clone()));
+			shutdown
+		}
+
+		/// Send a GET request for `path` over a new connection to `addr` and read the whole response
+		async fn raw_get(addr: &str, path: &str) -> std::io::Result<String> {
+			use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+			let mut stream = tokio::net::TcpStream::connect(addr).await?;
+			let request =
+				format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+			stream.write_all(request.as_bytes()).await?;
+			let mut response = String::new();
+			stream.read_to_string(&mut response).await?;
+			Ok(response)
+		}
+
+		#[tokio::test]
+		async fn bind_all() {
+			let addrs = ["127.0.0.1:0".to_string(), "127.0.0.1:0".to_string()];
+			let config = "[get_routes]\n\"a\" = \"a.txt\"\n";
+			let (dir, state) = load(config, &[("a.txt", "a")]).await;
+			let socket = SocketConfig::default();
+			let first = setup_listeners(addrs.iter(), &dir.0, socket, false).await;
+			assert_eq!(first.len(), 1);
+			let listeners = setup_listeners(addrs.iter(), &dir.0, socket, true).await;
+			assert_eq!(listeners.len(), 2);
+			for listener in listeners {
+				let addr = listener.address().unwrap();
+				let shutdown = spawn_server(state.clone(), listener);
+				let response = raw_get(&addr, "/a").await.unwrap();
+				assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+				assert!(response.ends_with("\r\n\r\na"), "{response}");
+				shutdown.cancel();
+			}
+		}
+
+		#[tokio::test]
+		async fn max_request_bytes() {
+			let config = "max_request_bytes = 4\n[get_routes]\n\"a\" = \"a.txt\"\n";
+			let (_dir, state) = load(config, &[("a.txt", "a")]).await;
+			let with_body = |method: Method, body: &'static str| {
+				let mut request = request(method, "/a", &[]);
+				let len = HeaderValue::from(body.len());
+				request.headers_mut().insert(CONTENT_LENGTH, len);
+				*request.body_mut() = Body::from(body);
+				request
+			};
+			let too_large = send(&state, with_body(Method::POST, "12345")).await;
+			assert_eq!(too_large.status(), StatusCode::PAYLOAD_TOO_LARGE);
+			let too_large = send(&state, with_body(Method::GET, "12345")).await;
+			assert_eq!(too_large.status(), StatusCode::PAYLOAD_TOO_LARGE);
+			let small = send(&state, with_body(Method::POST, "1234")).await;
+			assert_eq!(small.status(), StatusCode::METHOD_NOT_ALLOWED);
+			assert_eq!(get(&state, "/a", &[]).await.status(), StatusCode::OK);
+		}
+
+		#[tokio::test]
+		async fn keys_with_leading_slash() {
+			let config = "[get_routes]\n\"/a\" = \"a.txt\"\n\"b\" = \"b.txt\"\n";
+			let (_dir, state) = load(config, &[("a.txt", "a"), ("b.txt", "b")]).await;
+			assert_eq!(body(get(&state, "/a", &[]).await).await, "a");
+			assert_eq!(body(get(&state, "/b", &[]).await).await, "b");
+		}
+
+		#[tokio::test]
+		async fn percent_encoded_urls() {
+			let config = "[get_routes]\n\"my file.html\" = \"my file.html\"\n";
+			let (_dir, state) = load(config, &[("my file.html", "file")]).await;
+			assert_eq!(
+				body(get(&state, "/my%20file.html", &[]).await).await,
+				"file"
+			);
+			let nul = get(&state, "/my%20file.html%00", &[]).await;
+			assert_eq!(nul.status(), StatusCode::NOT_FOUND);
+		}
+
+		#[tokio::test]
+		async fn normalized_urls() {
+			let config = "[get_routes]\n\"a/b\" = \"a.txt\"\n";
+			let (_dir, state) = load(config, &[("a.txt", "a"), ("secret", "secret")]).await;
+			for uri in ["/a/b", "//a//b", "/a/./b", "/./a/b"] {
+				assert_eq!(body(get(&state, uri, &[]).await).await, "a", "{uri}");
+			}
+			for uri in ["/a/b/../b", "/a/../secret"] {
+				let response = get(&state, uri, &[]).await;
+				assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+			}
+		}
+
+		#[tokio::test]
+		async fn query_strings_are_ignored() {
+			let config = "[get_routes]\n\"page.html\" = \"page.html\"\n";
+			let (_dir, state) = load(config, &[("page.html", "page")]).await;
+			for uri in ["/page.html?foo=bar", "/page.html?", "/page.html?v=2&x"] {
+				let response = get(&state, uri, &[]).await;
+				assert_eq!(response.status(), StatusCode::OK, "{uri}");
+				assert_eq!(body(response).await, "page");
+			}
+		}
+
+		#[tokio::test]
+		async fn files_named_like_special_keys() {
+			let config = r#"
+				[get_routes]
+				direct = ["direct"]
+				[get_routes.unspecial]
+				"dirs" = "dirs.txt"
+			"#;
+			let (_dir, state) = load(config, &[("direct", "direct"), ("dirs.txt", "dirs")]).await;
+			// a file literally named `direct` is served at `/direct`
+			assert_eq!(body(get(&state, "/direct", &[]).await).await, "direct");
+			assert_eq!(body(get(&state, "/dirs", &[]).await).await, "dirs");
+			for uri in ["/%direct", "/%25direct"] {
+				assert_eq!(get(&state, uri, &[]).await.status(), StatusCode::NOT_FOUND);
+			}
+		}
+
+		#[test]
+		fn accepted_encodings() {
+			let accepts = |value: &str, encoding: &str| {
+				let headers = HeaderMap::from_iter([(ACCEPT_ENCODING, value.parse().unwrap())]);
+				accepts_encoding(&headers, encoding)
+			};
+			assert!(accepts("gzip, br", "br"));
+			assert!(accepts("GZIP;q=0.5", "gzip"));
+			assert!(accepts("*", "gzip"));
+			assert!(!accepts("br", "gzip"));
+			assert!(!accepts("gzip;q=0", "gzip"));
+			assert!(!accepts("*;q=0", "gzip"));
+			// an explicit entry takes priority over `*`, regardless of the order
+			assert!(!accepts("*, gzip;q=0", "gzip"));
+			assert!(!accepts("gzip;q=0, *", "gzip"));
+			assert!(accepts("*;q=0, gzip", "gzip"));
+			assert!(!accepts_encoding(&HeaderMap::new(), "gzip"));
+		}
+
+		#[tokio::test]
+		async fn etags_of_encodings() {
+			let config = r#"
+				compression = true
+				compression_algorithms = ["gzip", "br"]
+				[get_routes]
+				"a" = "a.txt"
+			"#;
+			let (_dir, state) = load(config, &[("a.txt", &"a".repeat(100))]).await;
+			let identity = get(&state, "/a", &[]).await;
+			let identity_etag = header(&identity, "etag").unwrap().to_string();
+			let gzip = get(&state, "/a", &[("accept-encoding", "gzip")]).await;
+			assert_eq!(header(&gzip, "content-encoding"), Some("gzip"));
+			let gzip_etag = header(&gzip, "etag").unwrap().to_string();
+			let br = get(&state, "/a", &[("accept-encoding", "br")]).await;
+			let br_etag = header(&br, "etag").unwrap().to_string();
+			assert!(gzip_etag.ends_with("-gzip\""), "{gzip_etag}");
+			assert!(br_etag.ends_with("-br\""), "{br_etag}");
+			assert_ne!(identity_etag, gzip_etag);
+			// only the representations compressed on the fly have weak ETags
+			assert!(identity_etag.starts_with('"'), "{identity_etag}");
+			assert!(gzip_etag.starts_with("W/\""), "{gzip_etag}");
+
+			// a cached uncompressed response doesn't match a request for a compressed one
+			let headers = [
+				("accept-encoding", "gzip"),
+				("if-none-match", &*identity_etag),
+			];
+			let response = get(&state, "/a", &headers).await;
+			assert_eq!(response.status(), StatusCode::OK);
+			let headers = [("accept-encoding", "gzip"), ("if-none-match", &*gzip_etag)];
+			let response = get(&state, "/a", &headers).await;
+			assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+			assert_eq!(header(&response, "etag"), Some(&*gzip_etag));
+
+			let headers = [("accept-encoding", "*, gzip;q=0")];
+			let response = get(&state, "/a", &headers).await;
+			assert_eq!(header(&response, "content-encoding"), Some("br"));
+		}
+
+		#[tokio::test]
+		async fn brotli_and_gzip() {
+			use std::io::Read;
+
+			let text = "a".repeat(100);
+			let files = [("a.txt", &*text), ("a.png", &*text)];
+			let routes = "[get_routes]\n\"a\" = \"a.txt\"\n\"png\" = \"a.png\"\n";
+			let config = format!("compression = true\n{routes}");
+			let (_dir, state) = load(&config, &files).await;
+			let both = [("accept-encoding", "gzip, br")];
+			let br = get(&state, "/a", &both).await;
+			assert_eq!(header(&br, "content-encoding"), Some("br"));
+			let mut decompressed = String::new();
+			let compressed = body(br).await;
+			brotli::Decompressor::new(&compressed[..], 4096)
+				.read_to_string(&mut decompressed)
+				.unwrap();
+			assert_eq!(decompressed, text);
+			let gzip = get(&state, "/a", &[("accept-encoding", "gzip")]).await;
+			assert_eq!(header(&gzip, "content-encoding"), Some("gzip"));
+			let png = get(&state, "/png", &both).await;
+			assert_eq!(header(&png, "content-encoding"), None);
+
+			let config = format!(
+				"compression = true\ncompression_algorithms = [\"gzip\", \"br\"]\n{routes}"
+			);
+			let (_dir, state) = load(&config, &files).await;
+			let gzip = get(&state, "/a", &both).await;
+			assert_eq!(header(&gzip, "content-encoding"), Some("gzip"));
+		}
+
+		#[tokio::test]
+		async fn precompressed_variants() {
+			let config = "precompressed = true\n[get_routes]\n\"style.css\" = \"style.css\"\n";
+			let files = [
+				("style.css", "plain"),
+				("style.css.br", "brotli"),
+				("style.css.gz", "gzip"),
+			];
+			let (dir, state) = load(config, &files).await;
+			let br = get(&state, "/style.css", &[("accept-encoding", "gzip, br")]).await;
+			assert_eq!(header(&br, "content-encoding"), Some("br"));
+			assert_eq!(header(&br, "content-type"), Some("text/css; charset=utf-8"));
+			assert_eq!(header(&br, "vary"), Some("Accept-Encoding"));
+			assert_eq!(body(br).await, "brotli");
+			let gzip = get(&state, "/style.css", &[("accept-encoding", "gzip")]).await;
+			assert_eq!(header(&gzip, "content-encoding"), Some("gzip"));
+			assert_eq!(body(gzip).await, "gzip");
+			let plain = get(&state, "/style.css", &[]).await;
+			assert_eq!(header(&plain, "content-encoding"), None);
+			assert_eq!(header(&plain, "vary"), Some("Accept-Encoding"));
+			assert_eq!(body(plain).await, "plain");
+
+			// without a variant, the file is sent as it is
+			std::fs::remove_file(dir.0.join("style.css.br")).unwrap();
+			let response = get(&state, "/style.css", &[("accept-encoding", "br")]).await;
+			assert_eq!(header(&response, "content-encoding"), None);
+			assert_eq!(body(response).await, "plain");
+		}
+
+		#[tokio::test]
+		async fn vary_only_when_negotiated() {
+			let routes = "[get_routes]\n\"a\" = \"a.txt\"\n\"png\" = \"a.png\"\n";
+			let files = [("a.txt", "a"), ("a.png", "png")];
+			let (_dir, state) = load(&format!("compression = true\n{routes}"), &files).await;
+			for headers in [&[][..], &[("accept-encoding", "gzip")]] {
+				let response = get(&state, "/a", headers).await;
+				assert_eq!(header(&response, "vary"), Some("Accept-Encoding"));
+			}
+			// images are never compressed
+			let png = get(&state, "/png", &[("accept-encoding", "gzip")]).await;
+			assert_eq!(header(&png, "vary"), None);
+			let not_found = get(&state, "/missing", &[("accept-encoding", "gzip")]).await;
+			assert_eq!(header(&not_found, "vary"), None);
+
+			let (_dir, state) = load(routes, &files).await;
+			let response = get(&state, "/a", &[("accept-encoding", "gzip")]).await;
+			assert_eq!(header(&response, "vary"), None);
+		}
+
+		#[test]
+		fn content_disposition_values() {
+			assert_eq!(content_disposition(None), "attachment");
+			let plain = content_disposition(Some("report.pdf"));
+			assert_eq!(plain, r#"attachment; filename="report.pdf""#);
+			let quoted = content_disposition(Some(r#"a"b\c.txt"#));
+			assert_eq!(
+				quoted,
+				r#"attachment; filename="a_b_c.txt"; filename*=UTF-8''a%22b%5Cc.txt"#
+			);
+			let unicode = content_disposition(Some("bericht ä.pdf"));
+			assert_eq!(
+				unicode,
+				r#"attachment; filename="bericht _.pdf"; filename*=UTF-8''bericht%20%C3%A4.pdf"#
+			);
+		}
+
+		#[tokio::test]
+		async fn downloads() {
+			let config = r#"
+				[get_routes]
+				"download" = { path = "files/report.pdf", download = true }
+				"named" = { path = "files/report.pdf", filename = "Bericht.pdf" }
+				"inline" = "files/report.pdf"
+			"#;
+			let (_dir, state) = load(config, &[("files/report.pdf", "pdf")]).await;
+			let download = get(&state, "/download", &[]).await;
+			let disposition = header(&download, "content-disposition");
+			assert_eq!(disposition, Some(r#"attachment; filename="report.pdf""#));
+			let named = get(&state, "/named", &[]).await;
+			let disposition = header(&named, "content-disposition");
+			assert_eq!(disposition, Some(r#"attachment; filename="Bericht.pdf""#));
+			let inline = get(&state, "/inline", &[]).await;
+			assert_eq!(header(&inline, "content-disposition"), None);
+			let unsatisfiable = get(&state, "/download", &[("range", "bytes=10-")]).await;
+			assert_eq!(unsatisfiable.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+			assert_eq!(header(&unsatisfiable, "content-disposition"), None);
+		}
+
+		#[tokio::test]
+		async fn file_events_per_server() {
+			use axum::body::HttpBody;
+
+			let config = "events_path = \"/__events\"\n";
+			let (_dir, config) = load_config(config, &[]);
+			let (_other_dir, other_config) = load_config("events_path = \"/__events\"\n", &[]);
+			let shutdown = CancellationToken::new();
+			let events = FileEvents {
+				changes: broadcast::channel(8).0,
+				shutdown: shutdown.clone(),
+			};
+			let shared = Arc::new(Shared {
+				file_events: Some(events),
+				..Shared::default()
+			});
+			let state = Arc::new(State::load(config, shared).await);
+			let response = get(&state, "/__events", &[]).await;
+			assert_eq!(header(&response, "content-type"), Some("text/event-stream"));
+			state.shared.file_events.as_ref().unwrap().notify("a.txt");
+			let mut body = response.into_body();
+			let frame = std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await;
+			let data = frame.unwrap().unwrap().into_data().unwrap();
+			assert_eq!(data, "data: a.txt\n\n");
+			shutdown.cancel();
+
+			// another server without watched files doesn't share the events
+			let other = Arc::new(State::load(other_config, Arc::default()).await);
+			let response = get(&other, "/__events", &[]).await;
+			assert_eq!(response.status(), StatusCode::NOT_FOUND);
+		}
+
+		#[tokio::test]
+		async fn multiple_addresses() {
+			let config = r#"
+				addr = ["127.0.0.1:0", ["127.0.0.1:0", "127.0.0.1:0"]]
+				bound_addr_file = "bound.txt"
+				max_requests = 2
+				[get_routes]
+				"a" = "a.txt"
+			"#;
+			let (dir, config) = try_load_config(config, &[("a.txt", "a")]);
+			let config = config.unwrap();
+			assert_eq!(config.addrs.len(), 2);
+			let server = tokio::spawn(serve(config));
+			let bound = dir.0.join("bound.txt");
+			let mut addrs = String::new();
+			for _ in 0..100 {
+				match std::fs::read_to_string(&bound) {
+					Ok(written) if written.ends_with('\n') => {
+						addrs = written;
+						break;
+					}
+					_ => tokio::time::sleep(Duration::from_millis(20)).await,
+				}
+			}
+			let addrs: Vec<_> = addrs.lines().collect();
+			// only the first working address of each group is listened on
+			assert_eq!(addrs.len(), 2);
+			for addr in addrs {
+				let response = raw_get(addr, "/a").await.unwrap();
+				assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+			}
+			// the server shuts down after `max_requests`
+			tokio::time::timeout(Duration::from_secs(5), server)
+				.await
+				.unwrap()
+				.unwrap();
+		}
+
+		/// Serve `config` (without `addr`) in a directory with `files` on a new port,
+		/// returning the address and a token that stops the server
+		async fn spawn_test_server(
+			config: &str,
+			files: &[(&str, &str)],
+		) -> (TestDir, String, CancellationToken) {
+			let (dir, state) = load(config, files).await;
+			let addrs = ["127.0.0.1:0".to_string()];
+			let socket = SocketConfig::default();
+			let listener = setup_listeners(addrs.iter(), &dir.0, socket, false).await;
+			let listener = listener.into_iter().next().unwrap();
+			let addr = listener.address().unwrap();
+			(dir, addr, spawn_server(state, listener))
+		}
+
+		#[tokio::test]
+		async fn date_header() {
+			let config = r#"
+				[redirects]
+				"old" = { to = "/a" }
+				[get_routes]
+				"a" = "a.txt"
+			"#;
+			let (_dir, addr, shutdown) = spawn_test_server(config, &[("a.txt", "a")]).await;
+			for (path, status) in [("/a", "200"), ("/missing", "404"), ("/old", "302")] {
+				let response = raw_get(&addr, path).await.unwrap();
+				assert!(
+					response.starts_with(&format!("HTTP/1.1 {status} ")),
+					"{response}"
+				);
+				let date = response
+					.lines()
+					.find_map(|l| l.strip_prefix("date: "))
+					.unwrap_or_else(|| panic!("no date in {response}"));
+				httpdate::parse_http_date(date).unwrap();
+			}
+			shutdown.cancel();
+		}
+
+		#[tokio::test]
+		async fn server_header() {
+			let routes =
+				"[get_routes]\n\"a\" = { path = \"a.txt\", headers = { Server = \"route\" } }\n";
+			let files = [("a.txt", "a")];
+			let cases = [
+				("", None),
+				("server_header = false\n", None),
+				("server_header = \"custom\"\n", Some("custom")),
+			];
+			for (config, expected) in cases {
+				let (_dir, state) = load(&format!("{config}{routes}"), &files).await;
+				let missing = get(&state, "/missing", &[]).await;
+				assert_eq!(header(&missing, "server"), expected, "{config}");
+			}
+			let (_dir, state) = load(&format!("server_header = true\n{routes}"), &files).await;
+			let missing = get(&state, "/missing", &[]).await;
+			let expected = concat!("simple-http-server/", env!("CARGO_PKG_VERSION"));
+			assert_eq!(header(&missing, "server"), Some(expected));
+
+			// the setting replaces a route's header, unless it is unset
+			let (_dir, state) =
+				load(&format!("server_header = \"custom\"\n{routes}"), &files).await;
+			assert_eq!(
+				header(&get(&state, "/a", &[]).await, "server"),
+				Some("custom")
+			);
+			let (_dir, state) = load(&format!("server_header = false\n{routes}"), &files).await;
+			assert_eq!(header(&get(&state, "/a", &[]).await, "server"), None);
+			let (_dir, state) = load(routes, &files).await;
+			assert_eq!(
+				header(&get(&state, "/a", &[]).await, "server"),
+				Some("route")
+			);
+		}
+
+		#[tokio::test]
+		async fn allowed_hosts() {
+			let config = r#"
+				allowed_hosts = ["example.com", "*.example.org"]
+				[get_routes]
+				"a" = "a.txt"
+			"#;
+			let (_dir, state) = load(config, &[("a.txt", "a")]).await;
+			for host in ["example.com", "example.com:8080", "www.example.org"] {
+				let response = get(&state, "/a", &[("host", host)]).await;
+				assert_eq!(response.status(), StatusCode::OK, "{host}");
+			}
+			for host in ["evil.com", "example.com.evil.com", "example.org"] {
+				let response = get(&state, "/a", &[("host", host)]).await;
+				assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST, "{host}");
+			}
+			let missing = get(&state, "/a", &[]).await;
+			assert_eq!(missing.status(), StatusCode::MISDIRECTED_REQUEST);
+			// HTTP/2 requests have the host in the URI
+			let response = get(&state, "http://example.com/a", &[]).await;
+			assert_eq!(response.status(), StatusCode::OK);
+		}
+
+		#[tokio::test]
+		async fn virtual_hosts() {
+			let config = r#"
+				404 = "404.html"
+				[get_routes]
+				"" = "index.html"
+				[[vhost]]
+				hosts = ["blog.example.com", "*.blog.example.com"]
+				404 = "blog/404.html"
+				[vhost.get_routes]
+				"" = "blog/index.html"
+				"post" = "blog/post.html"
+			"#;
+			let files = [
+				("index.html", "main"),
+				("404.html", "main 404"),
+				("blog/index.html", "blog"),
+				("blog/post.html", "post"),
+				("blog/404.html", "blog 404"),
+			];
+			let (_dir, state) = load(config, &files).await;
+			let blog = [("host", "blog.example.com")];
+			assert_eq!(body(get(&state, "/", &blog).await).await, "blog");
+			assert_eq!(body(get(&state, "/post", &blog).await).await, "post");
+			let sub = [("host", "www.blog.example.com:8080")];
+			assert_eq!(body(get(&state, "/", &sub).await).await, "blog");
+			let missing = get(&state, "/missing", &blog).await;
+			assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+			assert_eq!(body(missing).await, "blog 404");
+
+			// other hosts get the global routes
+			for headers in [&[("host", "example.com")][..], &[]] {
+				assert_eq!(body(get(&state, "/", headers).await).await, "main");
+				let post = get(&state, "/post", headers).await;
+				assert_eq!(post.status(), StatusCode::NOT_FOUND);
+				assert_eq!(body(post).await, "main 404");
+			}
+		}
+
+		#[tokio::test]
+		async fn if_range() {
+			let config = "[get_routes]\n\"a\" = \"a.txt\"\n";
+			let (_dir, state) = load(config, &[("a.txt", "0123456789")]).await;
+			let response = get(&state, "/a", &[]).await;
+			let etag = header(&response, "etag").unwrap().to_string();
+			let modified = header(&response, "last-modified").unwrap().to_string();
+
+			for if_range in [&*etag, &*modified] {
+				let headers = [("range", "bytes=2-4"), ("if-range", if_range)];
+				let response = get(&state, "/a", &headers).await;
+				assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT, "{if_range}");
+				assert_eq!(body(response).await, "234");
+			}
+			let old = "Thu, 01 Jan 1970 00:00:00 GMT";
+			for if_range in ["\"other\"", &*format!("W/{etag}"), old] {
+				let headers = [("range", "bytes=2-4"), ("if-range", if_range)];
+				let response = get(&state, "/a", &headers).await;
+				assert_eq!(response.status(), StatusCode::OK, "{if_range}");
+				assert_eq!(body(response).await, "0123456789");
+			}
+		}
+
+		#[tokio::test]
+		async fn max_connections() {
+			use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+			let config = "[http]\nmax_connections = 1\n[get_routes]\n\"a\" = \"a.txt\"\n";
+			let (_dir, addr, shutdown) = spawn_test_server(config, &[("a.txt", "a")]).await;
+			// keep the only allowed connection open
+			let mut first = tokio::net::TcpStream::connect(&addr).await.unwrap();
+			let request = "GET /a HTTP/1.1\r\nHost: localhost\r\n\r\n";
+			first.write_all(request.as_bytes()).await.unwrap();
+			let mut buf = [0; 1024];
+			let n = first.read(&mut buf).await.unwrap();
+			assert!(buf[..n].starts_with(b"HTTP/1.1 200"));
+
+			// further connections wait until the first one is closed
+			let second = tokio::spawn({
+				let addr = addr.clone();
+				async move { raw_get(&addr, "/a").await.unwrap() }
+			});
+			tokio::time::sleep(Duration::from_millis(100)).await;
+			assert!(!second.is_finished());
+
+			drop(first);
+			let response = tokio::time::timeout(Duration::from_secs(5), second).await;
+			let response = response.unwrap().unwrap();
+			assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+			shutdown.cancel();
+		}
+
+		#[tokio::test]
+		async fn fallback_handler() {
+			let config = r#"
+				[redirects]
+				"old" = { to = "/a" }
+				[get_routes]
+				"a" = "a.txt"
+			"#;
+			// answers every request except those for `/declined` with its body and a `name`
+			let echo = |name: &'static str| -> FallbackHandler {
+				Arc::new(move |request: Request<Body>| {
+					Box::pin(async move {
+						if request.uri().path() == "/declined" {
+							return None;
+						}
+						let body = axum::body::to_bytes(request.into_body(), usize::MAX).await;
+						Some(
+							format!("{name}: {}", String::from_utf8_lossy(&body.unwrap()))
+								.into_response(),
+						)
+					})
+				})
+			};
+			let (_dir, config) = load_config(config, &[("a.txt", "a")]);
+			let shared = |name| {
+				Arc::new(Shared {
+					fallback: Some(echo(name)),
+					..Shared::default()
+				})
+			};
+			let first = Arc::new(State::load(config.clone(), shared("first")).await);
+			let second = Arc::new(State::load(config, shared("second")).await);
+
+			let post = Request::post("/echo").body(Body::from("body")).unwrap();
+			let response = send(&first, post).await;
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(body(response).await, "first: body");
+			// every server has its own handler
+			assert_eq!(body(get(&second, "/echo", &[]).await).await, "second: ");
+			let response = send(&first, request(Method::DELETE, "/other", &[])).await;
+			assert_eq!(body(response).await, "first: ");
+
+			// routes and redirects take priority
+			assert_eq!(body(get(&first, "/a", &[]).await).await, "a");
+			assert_eq!(get(&first, "/old", &[]).await.status(), StatusCode::FOUND);
+			// declined requests are handled as without a handler
+			let declined = get(&first, "/declined", &[]).await;
+			assert_eq!(declined.status(), StatusCode::NOT_FOUND);
+			let declined = send(&first, request(Method::POST, "/declined", &[])).await;
+			assert_eq!(declined.status(), StatusCode::METHOD_NOT_ALLOWED);
+		}
+
+		#[tokio::test]
+		async fn templates() {
+			let config = r#"
+				[template_vars]
+				build = "abc123"
+				year = "2024"
+				[get_routes]
+				"page" = { path = "page.html", template = true }
+				"plain" = "page.html"
+				"image" = { path = "image.png", template = true }
+			"#;
+			let page = "<p>{{build}} ({{ year }}) {{unknown}}</p>";
+			let files = [("page.html", page), ("image.png", "{{build}}")];
+			let (_dir, state) = load(config, &files).await;
+			let response = get(&state, "/page", &[]).await;
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(header(&response, "etag"), None);
+			assert_eq!(header(&response, "last-modified"), None);
+			assert_eq!(body(response).await, "<p>abc123 (2024) {{unknown}}</p>");
+			// other routes and non-text files are served as they are
+			assert_eq!(body(get(&state, "/plain", &[]).await).await, page);
+			assert_eq!(body(get(&state, "/image", &[]).await).await, "{{build}}");
+		}
+
+		#[tokio::test]
+		async fn rewritten_routes() {
+			let config = r#"
+				[[rewrite]]
+				from = "/blog/:slug"
+				to = "/posts/:slug.html"
+				[get_routes]
+				"posts/*" = "posts/*"
+			"#;
+			let files = [("posts/hello.html", "hello"), ("posts/a b.html", "a b")];
+			let (_dir, state) = load(config, &files).await;
+			let response = get(&state, "/blog/hello", &[]).await;
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(
+				header(&response, "content-type"),
+				Some("text/html; charset=utf-8")
+			);
+			assert_eq!(body(response).await, "hello");
+			assert_eq!(body(get(&state, "/blog/a%20b", &[]).await).await, "a b");
+			for uri in ["/blog/missing", "/blog/hello/more", "/other/hello"] {
+				assert_eq!(
+					get(&state, uri, &[]).await.status(),
+					StatusCode::NOT_FOUND,
+					"{uri}"
+				);
+			}
+		}
+
+		#[tokio::test]
+		async fn accept_alternatives() {
+			let config = r#"
+				[get_routes]
+				"page" = ["page.html", { type = "application/json", path = "page.json" }]
+			"#;
+			let files = [("page.html", "<p>page</p>"), ("page.json", "{}")];
+			let (_dir, state) = load(config, &files).await;
+			let html = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+			let json = "application/json, text/html;q=0.5";
+			for (accept, expected) in [
+				(html, "<p>page</p>"),
+				("text/*", "<p>page</p>"),
+				(json, "{}"),
+				("*/*;q=0.1, application/json", "{}"),
+				// ties go to the first alternative
+				("*/*", "<p>page</p>"),
+			] {
+				let response = get(&state, "/page", &[("accept", accept)]).await;
+				assert_eq!(response.status(), StatusCode::OK, "{accept}");
+				assert_eq!(header(&response, "vary"), Some("Accept"), "{accept}");
+				assert_eq!(body(response).await, expected, "{accept}");
+			}
+			let json_response = get(&state, "/page", &[("accept", json)]).await;
+			assert_eq!(
+				header(&json_response, "content-type"),
+				Some("application/json")
+			);
+			assert_eq!(body(get(&state, "/page", &[]).await).await, "<p>page</p>");
+
+			for accept in ["image/png", "text/html;q=0, application/json;q=0"] {
+				let response = get(&state, "/page", &[("accept", accept)]).await;
+				assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE, "{accept}");
+			}
+		}
+
+		#[tokio::test]
+		async fn default_mime() {
+			let routes = "[get_routes]\n\"readme\" = \"README\"\n\"a\" = \"a.txt\"\n";
+			let files = [("README", "read me"), ("a.txt", "a")];
+			let (_dir, state) = load(routes, &files).await;
+			for method in [Method::GET, Method::HEAD] {
+				let response = send(&state, request(method.clone(), "/readme", &[])).await;
+				assert_eq!(response.status(), StatusCode::OK);
+				assert_eq!(header(&response, "content-type"), None, "{method}");
+				assert_eq!(header(&response, "content-length"), Some("7"), "{method}");
+			}
+
+			let config = format!("default_mime = \"text/plain; charset=utf-8\"\n{routes}");
+			let (_dir, state) = load(&config, &files).await;
+			let response = get(&state, "/readme", &[]).await;
+			let text = Some("text/plain; charset=utf-8");
+			assert_eq!(header(&response, "content-type"), text);
+			assert_eq!(body(response).await, "read me");
+			// inferred types take priority
+			let response = get(&state, "/a", &[]).await;
+			assert_eq!(header(&response, "content-type"), text);
+			let (_dir, state) =
+				load(&format!("default_mime = \"image/png\"\n{routes}"), &files).await;
+			let response = get(&state, "/a", &[]).await;
+			assert_eq!(header(&response, "content-type"), text);
+		}
+
+		#[tokio::test]
+		async fn nosniff() {
+			let routes = r#"
+				[get_routes]
+				"typed" = "a.txt"
+				"untyped" = "README"
+				"custom" = { path = "a.txt", headers = { "X-Content-Type-Options" = "custom" } }
+			"#;
+			let files = [("a.txt", "a"), ("README", "read me")];
+			let (_dir, state) = load(routes, &files).await;
+			let typed = get(&state, "/typed", &[]).await;
+			assert!(header(&typed, "content-type").is_some());
+			assert_eq!(header(&typed, "x-content-type-options"), Some("nosniff"));
+			let untyped = get(&state, "/untyped", &[]).await;
+			assert_eq!(header(&untyped, "content-type"), None);
+			assert_eq!(header(&untyped, "x-content-type-options"), None);
+			let custom = get(&state, "/custom", &[]).await;
+			assert_eq!(header(&custom, "x-content-type-options"), Some("custom"));
+
+			let (_dir, state) = load(&format!("nosniff = false\n{routes}"), &files).await;
+			let typed = get(&state, "/typed", &[]).await;
+			assert!(header(&typed, "content-type").is_some());
+			assert_eq!(header(&typed, "x-content-type-options"), None);
+		}
+
+		/// Serve the config file `config` (without `addr` and `tls`) over TLS
+		/// with a self-signed certificate for `localhost`.
+		///
+		/// Also returns the address and a client config that trusts the certificate.
+		#[cfg(feature = "tls")]
+		async fn spawn_tls_server(
+			config: &str,
+			files: &[(&str, &str)],
+		) -> (
+			TestDir,
+			SocketAddr,
+			rustls::ClientConfig,
+			axum_server::Handle,
+		) {
+			let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+			let (cert_pem, key_pem) = (cert.cert.pem(), cert.key_pair.serialize_pem());
+			let mut files = files.to_vec();
+			files.extend([("cert.pem", &*cert_pem), ("key.pem", &*key_pem)]);
+			let config = format!("{config}\n[tls]\ncert = \"cert.pem\"\nkey = \"key.pem\"\n");
+			let (dir, config) = load_config(&config, &files);
+			let tls = tls::load_tls(&config, config.tls.as_ref().unwrap())
+				.await
+				.unwrap();
+			let http = config.http;
+			let state = Arc::new(State::load(config, Arc::default()).await);
+			let handler = move |request: Request<Body>| {
+				let state = state.clone();
+				async move { app(&state, request).await }
+			};
+			let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+			let addr = listener.local_addr().unwrap();
+			let handle = axum_server::Handle::new();
+			tokio::spawn(serve_tls(
+				listener,
+				tls,
+				handler,
+				http,
+				None,
+				handle.clone(),
+			));
+
+			let mut roots = rustls::RootCertStore::empty();
+			roots.add(cert.cert.der().clone()).unwrap();
+			let client = rustls::ClientConfig::builder()
+				.with_root_certificates(roots)
+				.with_no_client_auth();
+			(dir, addr, client, handle)
+		}
+
+		/// Connect to `addr` over TLS (for `localhost`), offering the ALPN `protocols`
+		#[cfg(feature = "tls")]
+		async fn tls_connect(
+			addr: SocketAddr,
+			client: &rustls::ClientConfig,
+			protocols: &[&[u8]],
+		) -> std::io::Result<tokio_rustls::client::TlsStream<tokio::net::TcpStream>> {
+			let mut client = client.clone();
+			client.alpn_protocols = protocols.iter().map(|p| p.to_vec()).collect();
+			let connector = tokio_rustls::TlsConnector::from(Arc::new(client));
+			let tcp = tokio::net::TcpStream::connect(addr).await?;
+			let name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+			connector.connect(name, tcp).await
+		}
+
+		#[cfg(feature = "tls")]
+		#[tokio::test]
+		async fn tls_handshake() {
+			use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+			let config = "[get_routes]\n\"a\" = \"a.txt\"\n";
+			let (_dir, addr, client, handle) = spawn_tls_server(config, &[("a.txt", "a")]).await;
+			let mut stream = tls_connect(addr, &client, &[]).await.unwrap();
+			let request = "GET /a HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+			stream.write_all(request.as_bytes()).await.unwrap();
+			let mut response = String::new();
+			stream.read_to_string(&mut response).await.unwrap();
+			assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+			assert!(response.ends_with("\r\n\r\na"), "{response}");
+
+			// clients that don't trust the certificate can't connect
+			let untrusted = rustls::ClientConfig::builder()
+				.with_root_certificates(rustls::RootCertStore::empty())
+				.with_no_client_auth();
+			assert!(tls_connect(addr, &untrusted, &[]).await.is_err());
+			handle.shutdown();
+		}
+
+		/// Send a GET request for `uri` (which has to be absolute) over HTTP/2 on `io`
+		#[cfg(feature = "http2")]
+		async fn h2_get<I>(io: I, uri: &str) -> axum::response::Response
+		where
+			I: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+		{
+			let (mut sender, conn) =
+				hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(io))
+					.await
+					.unwrap();
+			tokio::spawn(conn);
+			let request = Request::get(uri).body(Body::empty()).unwrap();
+			sender.send_request(request).await.unwrap().map(Body::new)
+		}
+
+		#[cfg(feature = "http2")]
+		#[tokio::test]
+		async fn h2c_prior_knowledge() {
+			let routes = "[get_routes]\n\"a\" = \"a.txt\"\n";
+			let (_dir, addr, shutdown) = spawn_test_server(routes, &[("a.txt", "a")]).await;
+			let tcp = tokio::net::TcpStream::connect(&addr).await.unwrap();
+			let response = h2_get(tcp, "http://localhost/a").await;
+			assert_eq!(response.version(), Version::HTTP_2);
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(body(response).await, "a");
+			shutdown.cancel();
+
+			let config = format!("http2 = false\n{routes}");
+			let (_dir, addr, shutdown) = spawn_test_server(&config, &[("a.txt", "a")]).await;
+			let tcp = tokio::net::TcpStream::connect(&addr).await.unwrap();
+			let response = h2_get(tcp, "http://localhost/a").await;
+			assert_eq!(response.status(), StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+			shutdown.cancel();
+		}
+
+		#[cfg(all(feature = "tls", feature = "http2"))]
+		#[tokio::test]
+		async fn alpn() {
+			let routes = "[get_routes]\n\"a\" = \"a.txt\"\n";
+			let (_dir, addr, client, handle) = spawn_tls_server(routes, &[("a.txt", "a")]).await;
+			let stream = tls_connect(addr, &client, &[b"h2", b"http/1.1"])
+				.await
+				.unwrap();
+			assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+			let response = h2_get(stream, "https://localhost/a").await;
+			assert_eq!(response.version(), Version::HTTP_2);
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(body(response).await, "a");
+			handle.shutdown();
+
+			// without HTTP/2, only HTTP/1.1 is offered
+			let config = format!("http2 = false\n{routes}");
+			let (_dir, addr, client, handle) = spawn_tls_server(&config, &[("a.txt", "a")]).await;
+			let stream = tls_connect(addr, &client, &[b"h2", b"http/1.1"])
+				.await
+				.unwrap();
+			assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+			assert!(tls_connect(addr, &client, &[b"h2"]).await.is_err());
+			handle.shutdown();
+		}
+
+		#[tokio::test]
+		async fn metrics() {
+			let config = r#"
+				metrics_path = "/metrics"
+				[get_routes]
+				"a" = "a.txt"
+				"static/*" = "static/*"
+			"#;
+			let files = [("a.txt", "a"), ("static/b.txt", "b"), ("static/c.txt", "c")];
+			let (_dir, state) = load(config, &files).await;
+			for uri in [
+				"/a",
+				"/a",
+				"/static/b.txt",
+				"/static/c.txt",
+				"/static/missing",
+				"/missing",
+			] {
+				get(&state, uri, &[]).await;
+			}
+			let metrics =
+				String::from_utf8(body(get(&state, "/metrics", &[]).await).await.to_vec()).unwrap();
+			assert!(
+				metrics.contains("simple_http_server_requests_total 6\n"),
+				"{metrics}"
+			);
+			assert!(metrics.contains("simple_http_server_responses_total{class=\"4xx\"} 2\n"));
+			// hits are counted by route, and only for served files
+			assert!(metrics.contains("simple_http_server_route_hits_total{route=\"/a\"} 2\n"));
+			assert!(
+				metrics.contains("simple_http_server_route_hits_total{route=\"/static/*\"} 2\n")
+			);
+			assert_eq!(metrics.matches("route_hits_total{").count(), 2, "{metrics}");
+
+			// every server has its own counters
+			let (_dir, other) = load(config, &files).await;
+			let metrics =
+				String::from_utf8(body(get(&other, "/metrics", &[]).await).await.to_vec()).unwrap();
+			assert!(
+				metrics.contains("simple_http_server_requests_total 0\n"),
+				"{metrics}"
+			);
+		}
+
+		#[tokio::test]
+		async fn multiple_ranges() {
+			let config = "[get_routes]\n\"a\" = \"a.txt\"\n";
+			let (_dir, state) = load(config, &[("a.txt", "0123456789")]).await;
+			let headers = [("range", "bytes=5-6,0-1")];
+			let response = get(&state, "/a", &headers).await;
+			assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+			let content_type = header(&response, "content-type").unwrap();
+			let boundary = content_type
+				.strip_prefix("multipart/byteranges; boundary=")
+				.unwrap()
+				.to_string();
+			let len = header(&response, "content-length").unwrap().to_string();
+			let part = |range: &str, data: &str| {
+				format!(
+					"\r\n--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+					Content-Range: bytes {range}/10\r\n\r\n{data}"
+				)
+			};
+			// the parts are sorted
+			let expected =
+				part("0-1", "01") + &part("5-6", "56") + &format!("\r\n--{boundary}--\r\n");
+			assert_eq!(body(response).await, expected);
+			assert_eq!(len, expected.len().to_string());
+			let head = send(&state, request(Method::HEAD, "/a", &headers)).await;
+			assert_eq!(header(&head, "content-length"), Some(&*len));
+			assert!(body(head).await.is_empty());
+
+			// overlapping and adjacent ranges are merged
+			let response = get(&state, "/a", &[("range", "bytes=0-3,2-5,6-6")]).await;
+			assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+			assert_eq!(header(&response, "content-range"), Some("bytes 0-6/10"));
+			assert_eq!(body(response).await, "0123456");
+			let response = get(&state, "/a", &[("range", "bytes=8-9,0-1,1-2")]).await;
+			let body = String::from_utf8(body(response).await.to_vec()).unwrap();
+			assert!(
+				body.contains("Content-Range: bytes 0-2/10\r\n\r\n012\r\n"),
+				"{body}"
+			);
+			assert!(
+				body.contains("Content-Range: bytes 8-9/10\r\n\r\n89\r\n"),
+				"{body}"
+			);
+		}
+
+		#[tokio::test]
+		async fn too_many_ranges() {
+			let config = "[get_routes]\n\"a\" = \"a.txt\"\n";
+			let text = "a".repeat(200);
+			let (_dir, state) = load(config, &[("a.txt", &text)]).await;
+			let ranges = |n: usize| {
+				let ranges: Vec<_> = (0..n).map(|i| format!("{0}-{0}", i * 2)).collect();
+				format!("bytes={}", ranges.join(","))
+			};
+			let response = get(&state, "/a", &[("range", &ranges(64))]).await;
+			assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+			// more ranges than that are ignored
+			let response = get(&state, "/a", &[("range", &ranges(65))]).await;
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(body(response).await, text);
+		}
+
+		#[tokio::test]
+		async fn ranges_above_stream_threshold() {
+			let config = "stream_threshold = 3\n[get_routes]\n\"a\" = \"a.txt\"\n";
+			let (_dir, state) = load(config, &[("a.txt", "0123456789")]).await;
+			// the parts would have to be built in memory, so the whole file is sent instead
+			let response = get(&state, "/a", &[("range", "bytes=0-1,5-6")]).await;
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(header(&response, "content-range"), None);
+			assert_eq!(body(response).await, "0123456789");
+			// single ranges are streamed
+			let response = get(&state, "/a", &[("range", "bytes=0-4")]).await;
+			assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+			assert_eq!(body(response).await, "01234");
+		}
+
+		#[tokio::test]
+		async fn streamed_files_are_not_compressed() {
+			let config =
+				"compression = true\nstream_threshold = 10\n[get_routes]\n\"a\" = \"a.txt\"\n";
+			let text = "a".repeat(100);
+			let (_dir, state) = load(config, &[("a.txt", &text)]).await;
+			let identity = get(&state, "/a", &[]).await;
+			let identity_etag = header(&identity, "etag").unwrap().to_string();
+			let br = [("accept-encoding", "br")];
+			let response = get(&state, "/a", &br).await;
+			assert_eq!(header(&response, "content-encoding"), None);
+			assert_eq!(header(&response, "vary"), None);
+			assert_eq!(header(&response, "etag"), Some(&*identity_etag));
+			assert_eq!(body(response).await, text);
+
+			let headers = [br[0], ("if-none-match", &*identity_etag)];
+			let response = get(&state, "/a", &headers).await;
+			assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+		}
+	}
+}
+
+/// Helpers for the tests of all modules
+#[cfg(test)]
+mod testing {
+	use std::path::PathBuf;
+	use std::sync::atomic::{AtomicU64, Ordering};
+
+	use crate::cli::{Args, Mode};
+	use crate::config::{Config, ConfigError};
+	use crate::log::{self, LogLevel};
+
+	/// A temporary directory that is removed when this is dropped
+	pub struct TestDir(pub PathBuf);
+
+	impl TestDir {
+		/// A new directory containing `files` (paths relative to it and their contents)
+		pub fn new(files: &[(&str, &str)]) -> Self {
+			static COUNTER: AtomicU64 = AtomicU64::new(0);
+			let dir = std::env::temp_dir().join(format!(
+				"simple-http-server-test-{}-{}",
+				std::process::id(),
+				COUNTER.fetch_add(1, Ordering::Relaxed)
+			));
+			let this = Self(dir);
+			for (path, content) in files {
+				this.write(path, content);
+			}
+			this
+		}
+
+		/// Write `content` to the file at `path` (relative to this directory)
+		pub fn write(&self, path: &str, content: impl AsRef<[u8]>) {
+			let path = self.0.join(path);
+			std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+			std::fs::write(path, content).unwrap();
+		}
+	}
+
+	impl Drop for TestDir {
+		fn drop(&mut self) {
+			let _ = std::fs::remove_dir_all(&self.0);
+		}
+	}
+
+	/// Load the config file `config` (without `addr`) in a directory with `files`
+	pub fn load_config(config: &str, files: &[(&str, &str)]) -> (TestDir, Config) {
+		let config = format!("addr = \"127.0.0.1:0\"\n{config}");
+		let (dir, config) = try_load_config(&config, files);
+		(dir, config.unwrap())
+	}
+
+	/// Try to load the complete config file `config` in a directory with `files`
+	pub fn try_load_config(
+		config: &str,
+		files: &[(&str, &str)],
+	) -> (TestDir, Result<Config, ConfigError>) {
+		log::set_level(LogLevel::Error);
+		let dir = TestDir::new(files);
+		dir.write("config.toml", config);
+		let args = Args {
+			config: dir.0.join("config.toml"),
+			mode: Mode::Serve,
+			port: None,
+			log_level: None,
+			list_routes: false,
+			watch: false,
+			dev: false,
+		};
+		let config = Config::new(args);
+		(dir, config)
+	}
+}